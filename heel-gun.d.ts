@@ -1,16 +1,95 @@
 /// A heel-gun configuration file root.
 interface Config {
-    targets: TestTarget[]
+    targets: TestTarget[],
+    /// The base path of the endpoints of the targets without a prefix,
+    /// such as `/api/v2`
+    prefix?: string,
 }
 
 /// A specific test target of the tool.
 interface TestTarget {
     /// HTTP endpoint relative to the URI
     endpoint: string,
-    /// HTTP method
-    method: Method,
+    /// The base path of the endpoint, such as `/api/v2`
+    /// (overrides the prefix of the configuration)
+    prefix?: string,
+    /// HTTP method, or a list of methods to test the endpoint with
+    /// (as one target per method)
+    method: Method | Method[],
     /// The methods to randomly test
-    args: TestArg[]
+    args: TestArg[],
+    /// The generator of the request body (only used in POST, PUT and PATCH)
+    body?: ArgGenerator,
+    /// The content type of the request body
+    /// (default is "application/octet-stream")
+    content_type?: string,
+    /// The response status codes considered acceptable
+    /// (if omitted, only server errors are considered bad)
+    expect_status?: (number | StatusRange)[],
+    /// The response status codes considered bad, even if expected
+    /// (such as 2xx from a protected endpoint); server errors are always bad
+    forbid_status?: (number | StatusRange)[],
+    /// The strings which the body of each response must contain,
+    /// server errors aside (the response is bad otherwise)
+    body_must_contain?: string[],
+    /// The strings which the body of each response must not contain, such as
+    /// an error message in a 200 response (the response is bad otherwise)
+    body_must_not_contain?: string[],
+    /// The HTTP headers to include in each request
+    headers?: HeaderSpec[],
+    /// The number of iterations to test for this target
+    /// (overrides the global number of iterations)
+    iterations?: number,
+    /// Whether to place the sampled path and query string values in the URI
+    /// as they are, instead of percent-encoding characters not allowed there
+    /// (default is false). Requests with values which still do not make a
+    /// valid URI (such as with spaces or non-ASCII characters) are skipped,
+    /// and a `#` starts the fragment of the URI, which is not sent.
+    raw?: boolean,
+    /// The query string parameters to include in every request, after the
+    /// sampled ones, as `[name, value]` pairs of strings
+    fixed_query?: [string, string][],
+    /// How to frame the request body (default is "exact"):
+    /// - "overstate": declare a `Content-Length` greater than the body's length
+    /// - "understate": declare a `Content-Length` smaller than the body's length
+    /// - "chunked-malformed": send the body with a malformed chunked encoding
+    body_framing?: "exact" | "overstate" | "understate" | "chunked-malformed",
+    /// The compression of the request body, declared in the
+    /// `Content-Encoding` header (default is no compression)
+    compress?: "gzip" | "deflate",
+    /// The generator of the `Host` header, sent in place of the host of the
+    /// request URI (for testing virtual host routing)
+    host_header?: ArgGenerator,
+    /// The values to capture from the bodies of successful (2xx) responses,
+    /// for use by later targets with the `captured` generator
+    capture?: Capture[]
+}
+
+/// A value to capture from response bodies, with exactly one of
+/// `json_pointer` or `regex`.
+interface Capture {
+    /// the name under which the values are kept
+    name: string,
+    /// the JSON pointer to the value in a JSON body (such as "/id");
+    /// strings are taken as they are, other values as JSON
+    json_pointer?: string,
+    /// a regular expression matching the value, of which the first group
+    /// is taken (or the whole match if it has no groups)
+    regex?: string
+}
+
+/// An HTTP header to include in the request.
+interface HeaderSpec {
+    /// the header name
+    name: string,
+    /// the generator of the header value
+    value?: ArgGenerator
+}
+
+/// An inclusive range of HTTP status codes.
+interface StatusRange {
+    low: number,
+    high: number
 }
 
 /// HTTP method
@@ -18,7 +97,10 @@ declare enum Method {
     GET,
     POST,
     PUT,
-    DELETE
+    DELETE,
+    PATCH,
+    HEAD,
+    OPTIONS
 }
 
 /// An argument for generating a piece of the request.
@@ -29,21 +111,48 @@ interface TestArg {
 /// An argument that generates a part of the URI's path.
 interface PathTestArg extends TestArg {
     type?: "path",
-    generator: ArgGenerator
-
+    generator: ArgGenerator,
+    /// Whether the value may span several path segments, keeping its `/`
+    /// as they are (false by default)
+    multi_segment?: boolean,
 }
 
 /// An argument that generates one of the URI's query string key-values.
 interface QueryTestArg extends TestArg {
     type: "query"
-    key: ArgGenerator,
-    value: ArgGenerator
+    name?: ArgGenerator,
+    value?: ArgGenerator,
+    /// the number of times to include the parameter, each with a new value
+    /// (sampled as an integer, once if omitted or not a number)
+    repeat?: ArgGenerator,
+    /// the nested name in bracket notation, as in `name[nested]=value`
+    /// (an empty nested name builds an array, as in `name[]=value`)
+    nested?: ArgGenerator,
+    /// Whether the parameter is only included in some of the requests
+    /// (default is false)
+    optional?: boolean,
+    /// The probability of including an optional parameter in each request,
+    /// between 0 and 1 (default is 0.5). Giving it makes the parameter optional.
+    presence_chance?: number
+}
+
+/// An argument that appends a matrix parameter to the last path segment,
+/// as in `/items;name=value` (or `;name` alone for an empty value).
+interface MatrixTestArg extends TestArg {
+    type: "matrix",
+    name?: ArgGenerator,
+    value?: ArgGenerator
 }
 
 /// Argument generators describe the strategies for building (often random)
 /// components of a request.
 interface ArgGenerator {
-    type?: string
+    type?: string,
+    /// the probability of providing an empty value instead of a sampled one,
+    /// from 0 to 1 (default is 0). An empty query string value sends the
+    /// parameter name alone (as in `?name`), and an empty path segment
+    /// leaves an empty segment in the path (as in `/endpoint//`).
+    empty_chance?: number
 }
 
 /// Tries multiple random things, easy to use.
@@ -64,6 +173,20 @@ interface ChoiceArgGenerator extends ArgGenerator {
     values: string[]
 }
 
+/// Chooses one of the given arguments at random,
+/// in proportion to their weights.
+interface WeightedChoiceArgGenerator extends ArgGenerator {
+    type: "weighted_choice",
+    values: WeightedValue[]
+}
+
+/// A value of a weighted choice.
+interface WeightedValue {
+    value: string,
+    /// the relative likelihood of choosing this value (default is 1)
+    weight?: number
+}
+
 /// Chooses a random number from the given range.
 interface IntRangeArgGenerator extends ArgGenerator {
     type: "range",
@@ -73,20 +196,238 @@ interface IntRangeArgGenerator extends ArgGenerator {
     high: number,
 }
 
+/// Chooses a value around the bounds of the given range with high
+/// probability (the bounds, the values just outside them, zero, and the
+/// extreme 64-bit integers), or a random number from the range otherwise.
+interface BoundaryArgGenerator extends ArgGenerator {
+    type: "boundary",
+    /// low end, must be a 64-bit signed integer
+    low: number,
+    /// high end, must be a 64-bit signed integer
+    high: number,
+}
+
+/// Builds a sequence of numbers across iterations of the test:
+/// `start`, `start + step`, `start + 2 * step`, and so on.
+interface SequenceArgGenerator extends ArgGenerator {
+    type: "sequence",
+    /// the first number, must be a 64-bit signed integer (default is 0)
+    start?: number,
+    /// the increment, must be a 64-bit signed integer (default is 1)
+    step?: number
+}
+
+/// Chooses a random decimal number from the given range.
+/// The exact bounds are chosen more often.
+interface FloatRangeArgGenerator extends ArgGenerator {
+    type: "float_range",
+    /// low end
+    low: number,
+    /// high end
+    high: number,
+    /// the number of decimal places (default is full precision)
+    precision?: number
+}
+
+/// Chooses a random date from the given range, formatted with the given
+/// `strftime`-like format string. Edge dates (the bounds of the range,
+/// leap days, and the first and last days of a year) are chosen more often.
+interface DateArgGenerator extends ArgGenerator {
+    type: "date",
+    /// the date format (default is "%Y-%m-%d")
+    format?: string,
+    /// the earliest date, as YYYY-MM-DD (default is "1970-01-01")
+    from?: string,
+    /// the latest date, as YYYY-MM-DD (default is "2099-12-31")
+    to?: string
+}
+
+/// Chooses a random Unix timestamp in seconds from the given range.
+/// The bounds and other notable timestamps (such as 0 and the limits of
+/// 32-bit timestamps) are chosen more often.
+interface EpochArgGenerator extends ArgGenerator {
+    type: "epoch",
+    /// low end, must be a 64-bit signed integer (default is 0)
+    low?: number,
+    /// high end, must be a 64-bit signed integer (default is 2147483647)
+    high?: number
+}
+
 /// Builds a decimal numeric sequence with the given length.
 interface NumericArgGenerator extends ArgGenerator {
     type: "numeric",
     /// the number of digits, must be an unsigned integer
-    len: number
+    len: Length
 }
 
 /// Builds an alphanumeric sequence with the given length.
 interface AlphaNumericArgGenerator extends ArgGenerator {
     type: "alphanumeric",
     /// the number of digits, must be an unsigned integer
+    len: Length
+}
+
+/// A fixed length, or a range of lengths to choose from at random
+/// (bounds included, `min` may be 0). The whole request URI is limited to
+/// 65534 bytes, so values of the path or query string cannot be longer;
+/// longer values (such as for testing request size limits) can be sent
+/// in the body or in a header.
+type Length = number | {
+    min: number,
+    max: number
+}
+
+/// Builds a percent-encoded sequence of random Unicode characters with the
+/// given length, such as control characters, combining marks,
+/// bidirectional overrides and emoji.
+interface UnicodeArgGenerator extends ArgGenerator {
+    type: "unicode",
+    /// the number of characters, must be an unsigned integer
     len: number
 }
 
+/// Builds a random email address, either plausibly valid or deliberately
+/// malformed (such as with a repeated `@`, a very long local part,
+/// or a Unicode domain).
+interface EmailArgGenerator extends ArgGenerator {
+    type: "email",
+    /// whether to only build plausibly valid addresses (default is false)
+    valid_only?: boolean
+}
+
+/// Builds a random version 4 UUID.
+interface UuidArgGenerator extends ArgGenerator {
+    type: "uuid",
+    /// whether to separate the UUID groups with hyphens (default is true)
+    hyphens?: boolean
+}
+
+/// Builds a random IP address. Edge cases (such as `0.0.0.0`,
+/// `255.255.255.255`, loopback and private addresses) are chosen more often.
+interface IpArgGenerator extends ArgGenerator {
+    type: "ip",
+    /// whether to build IPv6 addresses instead of IPv4 (default is false)
+    v6?: boolean,
+    /// whether to add a CIDR prefix length, as in `10.0.0.0/8`
+    /// (default is false)
+    cidr?: boolean,
+    /// whether to build a malformed address half of the time, such as with
+    /// an octet out of range or a prefix length too long (default is false)
+    malformed?: boolean
+}
+
+/// Chooses one of the built-in payloads of the given kind of attack
+/// (such as `' OR 1=1--` for SQL injection or `../../etc/passwd` for
+/// path traversal). Use `raw: true` in the target to send path payloads
+/// without percent-encoding. In a header, payloads which cannot be sent in
+/// one (such as those with line breaks) are replaced with other payloads.
+interface AttackArgGenerator extends ArgGenerator {
+    type: "attack",
+    category: "sqli" | "xss" | "path_traversal" | "command_injection" | "format_string" | "xxe"
+}
+
+/// Builds a fragment which breaks the structure of a JSON document when the
+/// server embeds the value in one, such as `"},{"`, a lone quote or escape,
+/// or a run of unbalanced brackets.
+interface JsonFragmentArgGenerator extends ArgGenerator {
+    type: "json_fragment"
+}
+
+/// Builds a fragment which breaks the structure of an XML document when the
+/// server embeds the value in one, such as a bare `&`, an undefined entity,
+/// a CDATA breaker (`]]>`) or a run of unclosed tags.
+interface XmlFragmentArgGenerator extends ArgGenerator {
+    type: "xml_fragment"
+}
+
+/// Chooses one of the many spellings of true and false, such as `1`, `yes`,
+/// `on` or `TRUE`, or a near miss such as `2` or ` true`.
+interface BoolArgGenerator extends ArgGenerator {
+    type: "bool"
+}
+
+/// Chooses one of the many spellings of a missing value, such as `null`,
+/// `nil`, `None`, `undefined`, `\N` or the empty string.
+interface NullishArgGenerator extends ArgGenerator {
+    type: "nullish"
+}
+
+/// Applies a number of random mutations to a valid value, such as flipping
+/// a bit, replacing, inserting, deleting or duplicating a character, or
+/// swapping two adjacent characters. No control characters are introduced,
+/// so that mutated values can be sent in headers.
+interface MutateArgGenerator extends ArgGenerator {
+    type: "mutate",
+    /// the valid value to mutate
+    seed: string,
+    /// the number of mutations applied to each value (at most 1024)
+    mutations: number
+}
+
+/// Builds a random string matching the given regular expression.
+interface RegexArgGenerator extends ArgGenerator {
+    type: "regex",
+    pattern: string
+}
+
+/// Chooses one of the lines in the given file at random.
+/// Empty lines are skipped.
+interface WordlistArgGenerator extends ArgGenerator {
+    type: "wordlist",
+    /// path to the newline-delimited file
+    path: string
+}
+
+/// Builds a JSON document from the given template. Objects of the form
+/// `{"$generator": ArgGenerator}` are replaced by a sampled string, or by the
+/// sampled value as is when `"$raw": true` (such as for numbers).
+interface JsonArgGenerator extends ArgGenerator {
+    type: "json",
+    template: any
+}
+
+/// Concatenates the given literal strings and sampled values, in order.
+interface TemplateArgGenerator extends ArgGenerator {
+    type: "template",
+    parts: (string | ArgGenerator)[]
+}
+
+/// Chooses one of the lines in the given file at random, reading only a part
+/// of the file for each value (for files too big to be loaded). The file is
+/// read once on loading, keeping the position of one in every 256 lines, so
+/// that all lines are equally likely. Empty lines are skipped.
+interface WordlistStreamingArgGenerator extends ArgGenerator {
+    type: "wordlist_streaming",
+    /// path to the newline-delimited file
+    path: string
+}
+
+/// Samples the given generator, or provides an empty value with the given
+/// probability. Any generator with an `empty_chance` is wrapped in one.
+interface MaybeEmptyArgGenerator extends ArgGenerator {
+    type: "maybe_empty",
+    empty_chance: number,
+    generator: ArgGenerator
+}
+
+/// Chooses one of the values captured by earlier targets under the given
+/// name at random (empty if none were captured yet). The target is only
+/// tested once all requests to the targets before it are complete.
+interface CapturedArgGenerator extends ArgGenerator {
+    type: "captured",
+    name: string
+}
+
+/// Takes the value of the given environment variable (such as a secret token
+/// which should not be written in the configuration), read when the
+/// configuration is loaded. The variable may be written with a leading `$`,
+/// and it is an error for it not to be set without a default value.
+interface EnvArgGenerator extends ArgGenerator {
+    type: "env",
+    var: string,
+    default?: string
+}
+
 /// Chooses one of the given generators at random (OR).
 interface UnionArgGenerator extends ArgGenerator {
     type: "union",
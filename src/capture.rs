@@ -0,0 +1,131 @@
+//! Capturing values from responses, for use in requests to later targets.
+//!
+//! A target may capture values from the bodies of its successful responses
+//! (such as the ID of a created resource), which are then sampled by the
+//! `captured` generators of the targets after it.
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+
+/// Maximum number of values kept under each name, the oldest ones being
+/// dropped first
+const MAX_CAPTURED_VALUES: usize = 1000;
+
+/// A value to capture from the body of each successful response to a target
+#[derive(Debug, Clone, Deserialize)]
+pub struct Capture {
+    /// The name under which the values are kept
+    pub name: String,
+    /// The JSON pointer to the value in a JSON body (such as `/id`)
+    #[serde(default)]
+    pub json_pointer: Option<String>,
+    /// A regular expression matching the value in the body, of which the
+    /// first group is taken (or the whole match if it has no groups)
+    #[serde(default)]
+    pub regex: Option<String>,
+    /// the regular expression, compiled on preparation
+    #[serde(skip)]
+    compiled: Option<Regex>,
+    /// the values captured so far, shared with the generators using them
+    #[serde(skip)]
+    pub values: CapturedValues,
+}
+
+impl PartialEq for Capture {
+    fn eq(&self, other: &Capture) -> bool {
+        self.name == other.name && self.json_pointer == other.json_pointer && self.regex == other.regex
+    }
+}
+
+impl Capture {
+    /// Check the capture for problems, returning a description of each
+    /// problem found.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        match (&self.json_pointer, &self.regex) {
+            (Some(_), Some(_)) | (None, None) => {
+                problems.push(format!(
+                    "capture {}: exactly one of json_pointer and regex must be given",
+                    self.name
+                ));
+            }
+            (Some(pointer), None) if !pointer.is_empty() && !pointer.starts_with('/') => {
+                problems.push(format!(
+                    "capture {}: JSON pointer '{}' must start with '/'",
+                    self.name, pointer
+                ));
+            }
+            (None, Some(regex)) => {
+                if let Err(e) = Regex::new(regex) {
+                    problems.push(format!("capture {}: {}", self.name, e));
+                }
+            }
+            _ => {}
+        }
+        problems
+    }
+
+    /// Prepare the capture for use.
+    pub fn prepare(&mut self) -> Result<(), regex::Error> {
+        if let Some(regex) = &self.regex {
+            self.compiled = Some(Regex::new(regex)?);
+        }
+        Ok(())
+    }
+
+    /// Capture the value in the given response body, if it is found.
+    pub fn capture(&self, body: &[u8]) -> Option<String> {
+        let value = if let Some(pointer) = &self.json_pointer {
+            let document: Value = serde_json::from_slice(body).ok()?;
+            match document.pointer(pointer)? {
+                Value::String(value) => value.clone(),
+                // numbers and other values are kept in JSON
+                value => value.to_string(),
+            }
+        } else {
+            let regex = self.compiled.as_ref()?;
+            let text = String::from_utf8_lossy(body);
+            let captures = regex.captures(&text)?;
+            captures.get(1).or_else(|| captures.get(0))?.as_str().to_string()
+        };
+        self.values.push(value.clone());
+        Some(value)
+    }
+}
+
+/// The values captured under a name, shared by the target capturing them
+/// and the generators using them
+#[derive(Debug, Clone, Default)]
+pub struct CapturedValues(Arc<Mutex<Vec<String>>>);
+
+impl CapturedValues {
+    /// Keep a newly captured value.
+    pub fn push(&self, value: String) {
+        let mut values = self.0.lock().unwrap();
+        if values.len() >= MAX_CAPTURED_VALUES {
+            values.remove(0);
+        }
+        values.push(value);
+    }
+
+    /// Choose one of the values captured so far at random,
+    /// or an empty string if none were captured.
+    pub fn choose<R>(&self, rng: &mut R) -> String
+    where
+        R: Rng,
+    {
+        self.0.lock().unwrap().choose(rng).cloned().unwrap_or_default()
+    }
+}
+
+/// Captured values are run time state rather than configuration,
+/// so they are not compared.
+impl PartialEq for CapturedValues {
+    fn eq(&self, _other: &CapturedValues) -> bool {
+        true
+    }
+}
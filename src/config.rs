@@ -1,39 +1,214 @@
 use itertools::Itertools;
+use http::Uri;
 use serde::Deserialize;
-use serde_json;
-use serde_yaml;
+use serde_json::Value;
+use std::collections::HashMap;
 use std::ffi::OsStr;
-use std::fs::{read_to_string, File};
+use std::fmt;
+use std::fs::read_to_string;
+use std::io::{self, Read};
 use std::path::Path;
+use std::str::FromStr;
 
-use crate::target::{ArgGenerator, TestArg, TestTarget};
+use crate::capture::CapturedValues;
+use crate::target::{ArgGenerator, Method, TestArg, TestTarget};
 
 pub type DynError = Box<dyn std::error::Error + Send + Sync>;
 
+/// The problems found in a configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigErrors {
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let count = self.problems.len();
+        let plural = if count == 1 { "" } else { "s" };
+        write!(f, "{} problem{} found in the configuration:", count, plural)?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+/// The format of a configuration file
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ConfigFormat {
+    Json,
+    Yaml,
+    Toml,
+    /// a Play Framework (v2) "routes" file
+    Routes,
+    /// a plain list of routes, one per line
+    SimpleList,
+}
+
+impl ConfigFormat {
+    /// The format of a configuration file, by its name
+    pub fn of_path(path: &Path) -> Option<Self> {
+        if path.file_name() == Some(OsStr::new("routes")) {
+            return Some(ConfigFormat::Routes);
+        }
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Some(ConfigFormat::Json),
+            Some("yml") | Some("yaml") => Some(ConfigFormat::Yaml),
+            Some("toml") => Some(ConfigFormat::Toml),
+            Some("txt") => Some(ConfigFormat::SimpleList),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(ConfigFormat::Json),
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            "toml" => Ok(ConfigFormat::Toml),
+            "routes" => Ok(ConfigFormat::Routes),
+            "txt" => Ok(ConfigFormat::SimpleList),
+            _ => Err("Invalid configuration format: must be json, yaml, toml, routes or txt"),
+        }
+    }
+}
+
 /// Server testing configuration object
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub targets: Vec<TestTarget>,
+    /// The base path of the endpoints of the targets without a prefix
+    #[serde(default)]
+    pub prefix: Option<String>,
 }
 
 impl Config {
-    pub fn from_file<P: AsRef<Path>>(filepath: P) -> Result<Self, DynError> {
+    /// Load a configuration from a file.
+    /// The format is given by the file's name, unless one is given here.
+    /// A path of `-` reads the configuration from the standard input,
+    /// in which case the format must be given.
+    pub fn from_file<P: AsRef<Path>>(filepath: P, format: Option<ConfigFormat>) -> Result<Self, DynError> {
         let path = filepath.as_ref();
-        if path.file_name() == Some(OsStr::new("routes")) {
-            return Config::from_routes_file(path);
+        if path == Path::new("-") {
+            let format = format.ok_or("The format is required to read the configuration from standard input")?;
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            return Config::from_text(&text, format);
         }
-        let config = match path.extension().and_then(|ext| ext.to_str()) {
-            Some("json") => serde_json::from_reader(File::open(path)?)?,
-            Some("yml") | Some("yaml") => serde_yaml::from_reader(File::open(path)?)?,
-            _ => Err("Unsupported configuration file: must be .json .yml)")?,
+        let format = format
+            .or_else(|| ConfigFormat::of_path(path))
+            .ok_or(
+                "Unsupported configuration file: must be .json, .yml, .yaml, .toml or .txt, \
+                 or be named `routes` (otherwise give its format with --config-format)",
+            )?;
+        Config::from_text(&read_to_string(path)?, format)
+    }
+
+    /// Build a configuration from its text in the given format.
+    pub fn from_text(text: &str, format: ConfigFormat) -> Result<Self, DynError> {
+        let mut document = match format {
+            ConfigFormat::Routes => return Config::from_routes(text),
+            ConfigFormat::SimpleList => return Config::from_simple_list(text),
+            ConfigFormat::Json | ConfigFormat::Yaml => {
+                let document = Config::parse_document(text, format)?;
+                if is_openapi(&document) {
+                    return Config::from_openapi(&document);
+                }
+                document
+            }
+            ConfigFormat::Toml => toml::from_str(text)?,
         };
+        expand_method_lists(&mut document)?;
+        expand_empty_chances(&mut document);
+        let mut config: Config = serde_json::from_value(document)?;
+        config.prepare()?;
         Ok(config)
     }
 
+    /// Parse a JSON or YAML document into a generic document value.
+    fn parse_document(text: &str, format: ConfigFormat) -> Result<Value, DynError> {
+        match format {
+            ConfigFormat::Json => Ok(serde_json::from_str(text)?),
+            ConfigFormat::Yaml => Ok(serde_yaml::from_str(text)?),
+            _ => Err("Unsupported document format: must be json or yaml".into()),
+        }
+    }
+
+    /// Check all test targets for problems, reporting all of them at once.
+    pub fn validate(&self) -> Result<(), ConfigErrors> {
+        let problems: Vec<_> = self
+            .targets
+            .iter()
+            .enumerate()
+            .flat_map(|(i, target)| {
+                target.validate().into_iter().map(move |problem| {
+                    format!("target #{} ({} {}): {}", i + 1, target.method(), target.path(), problem)
+                })
+            })
+            .collect();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigErrors { problems })
+        }
+    }
+
+    /// Validate and prepare all test targets for sampling.
+    pub fn prepare(&mut self) -> Result<(), DynError> {
+        if let Some(prefix) = &self.prefix {
+            for target in self.targets.iter_mut().filter(|t| t.prefix.is_none()) {
+                target.prefix = Some(prefix.clone());
+            }
+        }
+        self.validate()?;
+        for target in &mut self.targets {
+            target.prepare()?;
+        }
+        self.link_captures()?;
+        Ok(())
+    }
+
+    /// Link the `captured` generators of each target to the values
+    /// captured by the targets before it.
+    fn link_captures(&mut self) -> Result<(), ConfigErrors> {
+        let mut captured: HashMap<String, CapturedValues> = HashMap::new();
+        let mut problems = Vec::new();
+        for (i, target) in self.targets.iter_mut().enumerate() {
+            let title = format!("target #{} ({} {})", i + 1, target.method(), target.path());
+            let mut uses_captures = false;
+            target.visit_captured(&mut |name, values| {
+                uses_captures = true;
+                match captured.get(name) {
+                    Some(earlier) => *values = earlier.clone(),
+                    None => problems.push(format!("{}: no earlier target captures '{}'", title, name)),
+                }
+            });
+            target.uses_captures = uses_captures;
+            for capture in &mut target.capture {
+                capture.values = captured.entry(capture.name.clone()).or_default().clone();
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigErrors { problems })
+        }
+    }
+
     /// Build a configuration based on a Play Framework (v2) "routes" file
     pub fn from_routes_file<P: AsRef<Path>>(filepath: P) -> Result<Self, DynError> {
         let text = read_to_string(filepath.as_ref())?;
+        Config::from_routes(&text)
+    }
 
+    /// Build a configuration based on the contents of a Play Framework (v2)
+    /// "routes" file (see `from_routes_file`).
+    pub fn from_routes(text: &str) -> Result<Self, DynError> {
         let targets: Vec<_> = text
             .lines()
             .map(|t| {
@@ -44,28 +219,31 @@ impl Config {
                 }
             })
             .filter(|l| !l.is_empty())
+            // methods are matched in any case
             .filter_map(|l| match l.split_whitespace().next_tuple() {
-                t @ Some(("*", _))
-                | t @ Some(("GET", _))
-                | t @ Some(("POST", _))
-                | t @ Some(("PUT", _))
-                | t @ Some(("DELETE", _)) => t,
+                Some((method, uri)) if method == "*" || method.parse::<Method>().is_ok() => {
+                    Some((method, uri))
+                }
                 _ => None,
             })
             .flat_map(|(method, uri)| {
                 if method == "*" {
-                    vec![("GET", uri), ("POST", uri), ("PUT", uri), ("DELETE", uri)]
+                    vec![
+                        ("GET", uri),
+                        ("POST", uri),
+                        ("PUT", uri),
+                        ("DELETE", uri),
+                        ("PATCH", uri),
+                        ("HEAD", uri),
+                        ("OPTIONS", uri),
+                    ]
                 } else {
                     vec![(method, uri)]
                 }
             })
             .map(|(method, uri)| {
                 let (endpoint, args) = Config::parse_route_uri(uri)?;
-                Ok(TestTarget {
-                    endpoint,
-                    method: method.parse()?,
-                    args,
-                })
+                Ok(TestTarget::new(endpoint, method.parse()?, args))
             })
             .flat_map(|r: Result<_, DynError>| {
                 if let Err(e) = &r {
@@ -77,27 +255,260 @@ impl Config {
             })
             .collect();
 
-        Ok(Config { targets })
+        Ok(Config { targets, prefix: None })
     }
 
-    fn parse_route_uri(uri: &str) -> Result<(String, Vec<TestArg>), DynError> {
+    /// Build a configuration based on a plain list of routes, one per line,
+    /// in the form `METHOD /path` (or just `/path` for a GET request).
+    /// Paths are tested as they are, without any arguments.
+    pub fn from_simple_list_file<P: AsRef<Path>>(filepath: P) -> Result<Self, DynError> {
+        let text = read_to_string(filepath.as_ref())?;
+        Config::from_simple_list(&text)
+    }
+
+    /// Build a configuration based on a plain list of routes
+    /// (see `from_simple_list_file`).
+    pub fn from_simple_list(text: &str) -> Result<Self, DynError> {
+        let targets = text
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .flat_map(|l| {
+                let r = Config::parse_list_line(l);
+                if let Err(e) = &r {
+                    eprintln!("{}", e);
+                    eprintln!("Ignoring route due to the previous error.");
+                }
+                r
+            })
+            .collect();
+
+        Ok(Config { targets, prefix: None })
+    }
+
+    /// Parse a line of a plain list of routes into a test target.
+    fn parse_list_line(line: &str) -> Result<TestTarget, DynError> {
+        let mut parts = line.split_whitespace();
+        let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(path), None, None) => ("GET", path),
+            (Some(method), Some(path), None) => (method, path),
+            _ => return Err(format!("Invalid route `{}`: expected `METHOD /path`", line).into()),
+        };
+        // full URLs (such as those of a crawler) are tested by their path
+        let path = match path.find("://") {
+            Some(i) => {
+                let rest = &path[i + 3..];
+                rest.find('/').map(|j| &rest[j..]).unwrap_or("/")
+            }
+            None => path,
+        };
+        Ok(TestTarget::new(path.to_string(), method.to_uppercase().parse()?, vec![]))
+    }
+
+    /// Build a configuration which replays the requests of a CSV failure log,
+    /// one target per request, each sent once as it was recorded.
+    /// Request bodies are not recorded, so they are not sent again.
+    /// The path of the recorded base URL (if the log has that column) is left
+    /// out of the endpoints, as it is added again by the base URL of the replay.
+    pub fn from_failure_log<P: AsRef<Path>>(filepath: P) -> Result<Self, DynError> {
+        let mut reader = csv::Reader::from_path(filepath)?;
+        let headers = reader.headers()?.clone();
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|h| h == name)
+                .ok_or_else(|| format!("Not a failure log: missing column `{}`", name))
+        };
+        let (method_column, uri_column) = (column("method")?, column("uri")?);
+        let base_url_column = column("base_url").ok();
+
+        let mut targets = Vec::new();
+        for record in reader.records() {
+            let record = record?;
+            let (method, uri) = match (record.get(method_column), record.get(uri_column)) {
+                (Some(method), Some(uri)) => (method, uri),
+                _ => continue,
+            };
+            let uri: Uri = uri.parse()?;
+            let mut endpoint = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+            let base_url = base_url_column.and_then(|i| record.get(i));
+            if let Some(base) = base_url.and_then(|base| base.parse::<Uri>().ok()) {
+                let base_path = base.path().trim_end_matches('/');
+                if let Some(rest) = endpoint.strip_prefix(base_path) {
+                    if rest.is_empty() || rest.starts_with('/') || rest.starts_with('?') {
+                        endpoint = rest;
+                    }
+                }
+            }
+            let mut target = TestTarget::new(endpoint.to_string(), method.parse()?, vec![]);
+            target.iterations = Some(1);
+            targets.push(target);
+        }
+
+        Ok(Config { targets, prefix: None })
+    }
+
+    /// Build a configuration based on an OpenAPI (v3) or Swagger (v2)
+    /// specification file, in JSON or YAML
+    pub fn from_openapi_file<P: AsRef<Path>>(filepath: P) -> Result<Self, DynError> {
+        let path = filepath.as_ref();
+        let format = match ConfigFormat::of_path(path) {
+            Some(format @ ConfigFormat::Json) | Some(format @ ConfigFormat::Yaml) => format,
+            _ => return Err("Unsupported document file: must be .json, .yml or .yaml".into()),
+        };
+        let document = Config::parse_document(&read_to_string(path)?, format)?;
+        Config::from_openapi(&document)
+    }
+
+    /// Build a configuration based on an OpenAPI (v3) or Swagger (v2)
+    /// specification, with one target per path and method
+    pub fn from_openapi(spec: &Value) -> Result<Self, DynError> {
+        let paths = spec
+            .get("paths")
+            .and_then(Value::as_object)
+            .ok_or("OpenAPI specification has no paths")?;
+
+        let mut targets = Vec::new();
+        for (uri, item) in paths {
+            let item = resolve_ref(spec, item);
+            let operations = match item.as_object() {
+                Some(operations) => operations,
+                None => continue,
+            };
+            let common_params = parameter_list(spec, item);
+            for (method, operation) in operations {
+                // also skips other path item fields, such as "parameters"
+                let method: Method = match method.parse() {
+                    Ok(method) => method,
+                    Err(_) => continue,
+                };
+                // operation parameters override path item parameters
+                let mut params = parameter_list(spec, operation);
+                for param in &common_params {
+                    let overridden = params
+                        .iter()
+                        .any(|p| p.get("name") == param.get("name") && p.get("in") == param.get("in"));
+                    if !overridden {
+                        params.push(param);
+                    }
+                }
+                let (endpoint, args) = Config::parse_openapi_uri(uri, &params);
+                targets.push(TestTarget::new(endpoint, method, args));
+            }
+        }
+
+        let mut config = Config { targets, prefix: None };
+        config.prepare()?;
+        Ok(config)
+    }
+
+    fn parse_openapi_uri(uri: &str, params: &[&Value]) -> (String, Vec<TestArg>) {
         let mut base_endpoint = String::with_capacity(uri.len());
         let mut args = Vec::new();
         let mut has_param = false;
         for component in uri.split('/') {
-            if component.contains('*') {
-                return Err(format!(
-                    "could not read URI '{}': routes with wildcard '*' are currently not supported",
-                    uri
-                )
-                .into());
+            if component.starts_with('{') && component.ends_with('}') {
+                // component parameter
+                let name = &component[1..component.len() - 1];
+                let generator = params
+                    .iter()
+                    .find(|p| {
+                        p.get("in").and_then(Value::as_str) == Some("path")
+                            && p.get("name").and_then(Value::as_str) == Some(name)
+                    })
+                    .map(|p| openapi_generator(p))
+                    .unwrap_or_default();
+                args.push(TestArg::Path {
+                    generator,
+                    multi_segment: false,
+                });
+                has_param = true;
+            } else if !has_param {
+                if !base_endpoint.is_empty() {
+                    base_endpoint.push('/');
+                }
+                base_endpoint.push_str(component);
+            } else {
+                args.push(TestArg::Path {
+                    generator: ArgGenerator::Fixed {
+                        value: component.to_owned(),
+                    },
+                    multi_segment: false,
+                })
             }
-            if component.starts_with(":") {
+        }
+
+        for param in params {
+            if param.get("in").and_then(Value::as_str) != Some("query") {
+                continue;
+            }
+            if let Some(name) = param.get("name").and_then(Value::as_str) {
+                args.push(TestArg::QueryString {
+                    name: ArgGenerator::Fixed {
+                        value: name.to_owned(),
+                    },
+                    value: openapi_generator(param),
+                    repeat: None,
+                    nested: None,
+                    optional: false,
+                    presence_chance: None,
+                });
+            }
+        }
+
+        (base_endpoint, args)
+    }
+
+    fn parse_route_uri(uri: &str) -> Result<(String, Vec<TestArg>), DynError> {
+        let mut base_endpoint = String::with_capacity(uri.len());
+        let mut args = Vec::new();
+        let mut has_param = false;
+        for component in split_route_uri(uri) {
+            if component.starts_with(':') {
                 // component parameter
                 args.push(TestArg::Path {
                     generator: ArgGenerator::Magic,
+                    multi_segment: false,
+                });
+                has_param = true;
+            } else if component.starts_with('*') {
+                // parameter matching the rest of the path, slashes included
+                let mut generator = ArgGenerator::Regex {
+                    pattern: ROUTE_WILDCARD_PATTERN.to_string(),
+                    compiled: None,
+                };
+                generator.prepare()?;
+                args.push(TestArg::Path {
+                    generator,
+                    multi_segment: true,
                 });
                 has_param = true;
+            } else if component.starts_with('$') {
+                // parameter matching a regular expression, as in `$id<[0-9]+>`
+                let pattern = component
+                    .find('<')
+                    .filter(|_| component.ends_with('>'))
+                    .map(|i| &component[i + 1..component.len() - 1])
+                    .ok_or_else(|| format!("could not read URI '{}': expected `$name<regex>`", uri))?;
+                let mut generator = ArgGenerator::Regex {
+                    pattern: pattern.to_string(),
+                    compiled: None,
+                };
+                generator
+                    .prepare()
+                    .map_err(|e| format!("could not read URI '{}': {}", uri, e))?;
+                // as in Play, the expression may match across segments
+                args.push(TestArg::Path {
+                    generator,
+                    multi_segment: true,
+                });
+                has_param = true;
+            } else if component.contains('*') {
+                return Err(format!(
+                    "could not read URI '{}': wildcard '*' must start a path segment",
+                    uri
+                )
+                .into());
             } else if !has_param {
                 if !base_endpoint.is_empty() {
                     base_endpoint.push('/');
@@ -108,6 +519,7 @@ impl Config {
                     generator: ArgGenerator::Fixed {
                         value: component.to_owned(),
                     },
+                    multi_segment: false,
                 })
             }
         }
@@ -115,3 +527,156 @@ impl Config {
         Ok((base_endpoint, args))
     }
 }
+
+/// The pattern of the values sampled for the wildcard (`*name`) parameters
+/// of Play routes: a long path of several segments
+const ROUTE_WILDCARD_PATTERN: &str = "[A-Za-z0-9._~-]{1,16}(/[A-Za-z0-9._~-]{1,16}){1,15}";
+
+/// Split the URI of a Play route into its path segments.
+/// The regular expressions of `$name<regex>` parameters may contain `/`,
+/// which does not split them.
+fn split_route_uri(uri: &str) -> Vec<&str> {
+    let mut components = Vec::new();
+    let mut start = 0;
+    let mut in_regex = false;
+    for (i, c) in uri.char_indices() {
+        match c {
+            '<' if uri[start..].starts_with('$') => in_regex = true,
+            '>' => in_regex = false,
+            '/' if !in_regex => {
+                components.push(&uri[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    components.push(&uri[start..]);
+    components
+}
+
+/// Expand the targets of a configuration document with a list of methods
+/// into one target per method, in the same position.
+fn expand_method_lists(document: &mut Value) -> Result<(), DynError> {
+    let targets = match document.get_mut("targets").and_then(Value::as_array_mut) {
+        Some(targets) => targets,
+        None => return Ok(()),
+    };
+    let mut expanded = Vec::with_capacity(targets.len());
+    for target in targets.drain(..) {
+        let methods = match target.get("method") {
+            Some(Value::Array(methods)) => methods.clone(),
+            _ => {
+                expanded.push(target);
+                continue;
+            }
+        };
+        if methods.is_empty() {
+            return Err("Empty list of methods in a target".into());
+        }
+        for method in methods {
+            let mut target = target.clone();
+            target["method"] = method;
+            expanded.push(target);
+        }
+    }
+    *targets = expanded;
+    Ok(())
+}
+
+/// Wrap the generators of a configuration document with an `empty_chance`
+/// in `maybe_empty` generators, which sample them or an empty value.
+fn expand_empty_chances(value: &mut Value) {
+    match value {
+        Value::Array(items) => items.iter_mut().for_each(expand_empty_chances),
+        Value::Object(map) => {
+            let is_json = map.get("type").and_then(Value::as_str) == Some("json");
+            for (key, value) in map.iter_mut() {
+                if is_json && key == "template" {
+                    expand_template_empty_chances(value);
+                } else {
+                    expand_empty_chances(value);
+                }
+            }
+            if let Some(chance) = map.remove("empty_chance") {
+                let generator = Value::Object(std::mem::take(map));
+                map.insert("type".to_string(), "maybe_empty".into());
+                map.insert("empty_chance".to_string(), chance);
+                map.insert("generator".to_string(), generator);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Expand the generators with an `empty_chance` in a JSON template,
+/// leaving its fixed values as they are.
+fn expand_template_empty_chances(value: &mut Value) {
+    match value {
+        Value::Object(map) if map.contains_key("$generator") => {
+            expand_empty_chances(&mut map["$generator"]);
+        }
+        Value::Object(map) => map.values_mut().for_each(expand_template_empty_chances),
+        Value::Array(values) => values.iter_mut().for_each(expand_template_empty_chances),
+        _ => {}
+    }
+}
+
+/// Check whether the document is an OpenAPI or Swagger specification.
+fn is_openapi(document: &Value) -> bool {
+    document.get("openapi").is_some() || document.get("swagger").is_some()
+}
+
+/// Follow a local reference (`$ref: "#/..."`) in an OpenAPI specification,
+/// or return the value itself if it is not a reference.
+fn resolve_ref<'a>(spec: &'a Value, value: &'a Value) -> &'a Value {
+    value
+        .get("$ref")
+        .and_then(Value::as_str)
+        .filter(|r| r.starts_with('#'))
+        .and_then(|r| spec.pointer(&r[1..]))
+        .unwrap_or(value)
+}
+
+/// Obtain the (resolved) parameters of an OpenAPI path item or operation.
+fn parameter_list<'a>(spec: &'a Value, value: &'a Value) -> Vec<&'a Value> {
+    value
+        .get("parameters")
+        .and_then(Value::as_array)
+        .map(|params| params.iter().map(|p| resolve_ref(spec, p)).collect())
+        .unwrap_or_default()
+}
+
+/// Choose an argument generator for an OpenAPI parameter based on its schema.
+fn openapi_generator(param: &Value) -> ArgGenerator {
+    // OpenAPI v3 keeps the type in `schema`, Swagger v2 in the parameter
+    let schema = param.get("schema").unwrap_or(param);
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        return ArgGenerator::Choice {
+            values: values
+                .iter()
+                .map(|v| match v {
+                    Value::String(s) => s.clone(),
+                    v => v.to_string(),
+                })
+                .collect(),
+        };
+    }
+    match schema.get("type").and_then(Value::as_str) {
+        Some("integer") => {
+            let minimum = schema.get("minimum").and_then(Value::as_i64);
+            let maximum = schema.get("maximum").and_then(Value::as_i64);
+            // a missing bound defaults to one which keeps the range valid
+            ArgGenerator::IntRange {
+                low: minimum.unwrap_or_else(|| maximum.unwrap_or(0).min(-100000)),
+                high: maximum.unwrap_or_else(|| minimum.unwrap_or(0).max(100000)),
+            }
+        }
+        Some("boolean") => ArgGenerator::Choice {
+            values: vec!["true".to_string(), "false".to_string()],
+        },
+        Some("string") if schema.get("format").and_then(Value::as_str) == Some("uuid") => {
+            ArgGenerator::Uuid { hyphens: true }
+        }
+        _ => ArgGenerator::Magic,
+    }
+}
@@ -0,0 +1,163 @@
+//! Sending request bodies with deliberately wrong framing.
+//!
+//! hyper always frames request bodies correctly, so requests with a different
+//! body framing go through a connector whose transport sends a raw request
+//! built here instead of the bytes written by hyper. The response is still
+//! read by hyper as usual.
+
+use futures::prelude::*;
+use http::header::{CONNECTION, CONTENT_LENGTH, HOST, TRANSFER_ENCODING};
+use hyper::client::connect::{Connect, Connected, Destination};
+use hyper::client::HttpConnector;
+use hyper::{Body, Request};
+use hyper_tls::HttpsConnector;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use crate::request::OverrideResolver;
+use crate::target::BodyFraming;
+
+/// The transport wrapped by the connector of requests with a wrong body
+/// framing, which honours the TLS settings and address overrides of the
+/// other requests
+pub type FramingTransport = HttpsConnector<HttpConnector<OverrideResolver>>;
+
+/// Build a raw HTTP/1.1 request with the given body, framed as requested.
+/// The iteration number chooses among the kinds of malformed chunked
+/// encodings.
+pub fn raw_request(req: &Request<Body>, body: &[u8], framing: BodyFraming, iteration: u32) -> Vec<u8> {
+    let path = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    let mut raw = format!("{} {} HTTP/1.1\r\n", req.method(), path).into_bytes();
+    if !req.headers().contains_key(HOST) {
+        if let Some(authority) = req.uri().authority_part() {
+            raw.extend_from_slice(format!("host: {}\r\n", authority).as_bytes());
+        }
+    }
+    for (name, value) in req.headers() {
+        if name == CONTENT_LENGTH || name == TRANSFER_ENCODING || name == CONNECTION {
+            continue;
+        }
+        raw.extend_from_slice(name.as_str().as_bytes());
+        raw.extend_from_slice(b": ");
+        raw.extend_from_slice(value.as_bytes());
+        raw.extend_from_slice(b"\r\n");
+    }
+    raw.extend_from_slice(b"connection: close\r\n");
+
+    let len = body.len();
+    // the headers which frame the body and what follows the body
+    let content_length = |len: usize| (format!("content-length: {}\r\n\r\n", len), "");
+    let chunked =
+        |size: String, end: &'static str| (format!("transfer-encoding: chunked\r\n\r\n{}\r\n", size), end);
+    let (head, tail) = match framing {
+        BodyFraming::Exact => content_length(len),
+        BodyFraming::Overstate => content_length(len * 2 + 1),
+        BodyFraming::Understate => content_length(len / 2),
+        BodyFraming::ChunkedMalformed => match iteration % 4 {
+            // chunk size off by one
+            0 => chunked(format!("{:x}", len + 1), "\r\n0\r\n\r\n"),
+            // chunk size which is not a hexadecimal number
+            1 => chunked(format!("{}zz", len), "\r\n0\r\n\r\n"),
+            // no last chunk
+            2 => chunked(format!("{:x}", len), "\r\n"),
+            // conflicting content length
+            _ => {
+                let (head, tail) = chunked(format!("{:x}", len), "\r\n0\r\n\r\n");
+                (format!("content-length: {}\r\n{}", len / 2, head), tail)
+            }
+        },
+    };
+    raw.extend_from_slice(head.as_bytes());
+    raw.extend_from_slice(body);
+    raw.extend_from_slice(tail.as_bytes());
+    raw
+}
+
+/// A connector sending the given raw request on its connections
+#[derive(Debug, Clone)]
+pub struct FramingConnector<C> {
+    inner: C,
+    raw: Arc<Vec<u8>>,
+}
+
+impl<C> FramingConnector<C> {
+    pub fn new(inner: C, raw: Vec<u8>) -> Self {
+        FramingConnector {
+            inner,
+            raw: Arc::new(raw),
+        }
+    }
+}
+
+impl<C> Connect for FramingConnector<C>
+where
+    C: Connect,
+    C::Future: 'static,
+{
+    type Transport = FramedStream<C::Transport>;
+    type Error = C::Error;
+    type Future = Box<dyn Future<Item = (Self::Transport, Connected), Error = C::Error> + Send>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let raw = self.raw.clone();
+        Box::new(self.inner.connect(dst).map(move |(inner, connected)| {
+            let stream = FramedStream {
+                inner,
+                raw,
+                written: 0,
+            };
+            (stream, connected)
+        }))
+    }
+}
+
+/// A transport which writes a raw request in place of anything written to it
+#[derive(Debug)]
+pub struct FramedStream<T> {
+    inner: T,
+    raw: Arc<Vec<u8>>,
+    /// the number of bytes of the raw request written so far
+    written: usize,
+}
+
+impl<T: Write> FramedStream<T> {
+    /// Write what is left of the raw request.
+    fn write_raw(&mut self) -> io::Result<()> {
+        while self.written < self.raw.len() {
+            let n = self.inner.write(&self.raw[self.written..])?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.written += n;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Read> Read for FramedStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<T: Write> Write for FramedStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_raw()?;
+        // the bytes written by hyper are dropped
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_raw()?;
+        self.inner.flush()
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for FramedStream<T> {}
+
+impl<T: AsyncWrite> AsyncWrite for FramedStream<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.inner.shutdown()
+    }
+}
@@ -0,0 +1,706 @@
+#![deny(unsafe_code)]
+// the `Fail` derive from `failure` defines impls inside an anonymous const
+#![allow(non_local_definitions)]
+//! Testing of HTTP server robustness to arbitrary requests.
+//!
+//! A [`Config`] describes a set of test targets, from which random requests
+//! are built. Running the targets produces a stream of outcomes, which can
+//! be inspected for bad responses from the server.
+
+use std::io::Error as IoError;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use failure::Fail;
+use futures::future::{loop_fn, result, Either, Loop};
+use futures::prelude::*;
+use futures::stream::iter_ok;
+use http::uri::{InvalidUri, Uri};
+use http::header::LOCATION;
+use http::Error as HttpError;
+use hyper::client::connect::Connect;
+use hyper::client::HttpConnector;
+use hyper::error::Error as HyperError;
+use hyper::{Body, Client, Method, Request, StatusCode};
+use log::{info, warn};
+use rand::SeedableRng;
+use tokio::timer::timeout::{Error as TimeoutError, Timeout};
+use tokio::timer::Delay;
+
+pub mod capture;
+pub mod config;
+pub mod framing;
+pub mod outcome;
+pub mod report;
+pub mod request;
+pub mod rng;
+pub mod shrink;
+pub mod target;
+
+pub use config::Config;
+pub use outcome::{OutcomeKind, ServerOutcome, TargetOutcome};
+pub use target::TestTarget;
+
+use framing::{raw_request, FramingConnector, FramingTransport};
+use request::{decoded_body, resolve_location, retry_after, CookieJar, RequestSpec};
+use target::BodyFraming;
+
+/// Errors obtained from target testing
+#[derive(Debug, Fail)]
+pub enum Error {
+    /// some other error occurred while handling the HTTP stream
+    #[fail(display = "HTTP stream error: {}", err)]
+    HttpStream {
+        /// the HTTP method of the request performed
+        method: Method,
+        /// the URI of the HTTP request performed
+        uri: Uri,
+        err: HyperError,
+    },
+    /// some other error occurred while building HTTP content
+    #[fail(display = "HTTP error: {}", err)]
+    Http {
+        /// the HTTP method of the request performed
+        method: Method,
+        /// the URI of the HTTP request performed
+        uri: Uri,
+        err: HttpError,
+    },
+    /// some error occurred while fetching a response body
+    #[fail(display = "Hyper error: {}", err)]
+    Hyper {
+        err: HyperError,
+    },
+    /// some error occurred while doing disk I/O 
+    #[fail(display = "I/O error: {}", err)]
+    Io {
+        err: std::io::Error,
+    },
+    /// the target test sampler produced an illegal URI
+    #[fail(display = "Invalid request URI: {}", err)]
+    InvalidRequest {
+        #[fail(cause)]
+        err: InvalidUri,
+    },
+    /// the test target has problems which keep it from being sampled
+    #[fail(display = "Invalid test target {}: {}", target, problems)]
+    InvalidTarget {
+        /// the method and endpoint of the target
+        target: String,
+        /// the problems found, separated by `; `
+        problems: String,
+    },
+    /// the timer used for request timeouts failed
+    #[fail(display = "Timer error: {}", err)]
+    Timer {
+        #[fail(cause)]
+        err: tokio::timer::Error,
+    },
+    /// could not write a failure entry to disk
+    #[fail(display = "Failed to write outcome: {}", err)]
+    WriteFailure {
+        #[fail(cause)]
+        err: csv::Error,
+    },
+    /// could not write a failure entry to disk in JSON
+    #[fail(display = "Failed to write outcome: {}", err)]
+    WriteJsonFailure {
+        #[fail(cause)]
+        err: serde_json::Error,
+    },
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::WriteJsonFailure { err }
+    }
+}
+
+impl From<InvalidUri> for Error {
+    fn from(err: InvalidUri) -> Self {
+        Error::InvalidRequest { err }
+    }
+}
+
+impl From<IoError> for Error {
+    fn from(err: IoError) -> Self {
+        Error::Io { err }
+    }
+}
+
+impl From<HyperError> for Error {
+    fn from(err: HyperError) -> Self {
+        Error::Hyper { err }
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(err: csv::Error) -> Self {
+        Error::WriteFailure { err }
+    }
+}
+
+/// Options affecting how the requests to each test target are performed
+#[derive(Debug, Clone, Default)]
+pub struct RequestOptions {
+    /// maximum time to wait for each response
+    pub timeout: Option<Duration>,
+    /// maximum number of redirects to follow
+    pub max_redirects: u32,
+    /// HTTP headers to include in all requests, before the target's headers
+    pub headers: Vec<(String, String)>,
+    /// maximum number of times to retry a request after a connection error
+    pub retries: u32,
+    /// the cookie jar shared by all requests, if cookies are kept
+    pub cookies: Option<Arc<Mutex<CookieJar>>>,
+    /// time to wait between consecutive requests to the same target
+    pub delay: Option<Duration>,
+    /// whether rate limited responses (`429`) are fine even if
+    /// the target does not expect them
+    pub rate_limit_ok: bool,
+    /// whether server errors (`5xx`) are fine, so that only
+    /// connection failures and timeouts are counted as failures
+    pub ignore_5xx: bool,
+    /// the time after which no more requests are sent
+    /// (those in flight are still waited for)
+    pub deadline: Option<Instant>,
+    /// number of requests to send to each target before the tested ones,
+    /// with their outcomes marked as warm-up (and numbered from 0,
+    /// as are the tested ones)
+    pub warmup: u32,
+    /// the transport of requests with a deliberately wrong body framing,
+    /// or a plain HTTP connector if none is given
+    pub framing_transport: Option<FramingTransport>,
+}
+
+/// Maximum time to pause a target after a rate limited response
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+/// Time to wait before the first retry of a request,
+/// doubled on each subsequent retry
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// Sample a request to the given test target, as the given iteration of the
+/// test, without sending it. Any random number generator can drive the
+/// sampling, including a `ByteRng` fed with the input of a fuzzer.
+/// The target is not validated here, as this is done for every request:
+/// it must come from a configuration loaded with `Config::from_file` or
+/// `Config::from_text`, or have been prepared with `TestTarget::prepare`,
+/// as sampling may panic otherwise.
+pub fn sample_request<R>(
+    target: &TestTarget,
+    base_url: &str,
+    iteration: u32,
+    rng: &mut R,
+    global_headers: &[(String, String)],
+) -> Result<RequestSpec, Error>
+where
+    R: rand::Rng,
+{
+    let uri = target.sample(base_url, iteration, rng)?;
+    info!(
+        iteration = iteration, method:% = target.method(), uri:% = uri;
+        "{:4} > {:?} {:?}", iteration, target.method(), uri
+    );
+    let headers = target.sample_headers(iteration, rng);
+    let body = target.sample_body(iteration, rng);
+    Ok(RequestSpec {
+        method: target.method(),
+        uri,
+        headers: global_headers.iter().cloned().chain(headers).collect(),
+        body,
+        content_type: target.content_type.clone(),
+        compress: target.compress,
+    })
+}
+
+/// Obtain a stream of the requests sampled from a test target, without
+/// sending them. Each item holds the iteration number, the base URL used,
+/// and the request both as its parts and built. Requests which cannot be
+/// built (as with a header value which cannot be sent, or a raw value which
+/// does not make a valid URI) are skipped, with a warning. The target is validated first, so that the stream fails with
+/// `Error::InvalidTarget` if it has any problem.
+pub fn sample_requests(
+    base_urls: Arc<[String]>,
+    target: Arc<TestTarget>,
+    niter: u32,
+    seed: u64,
+    global_headers: Vec<(String, String)>,
+) -> impl Stream<Item = (u32, String, RequestSpec, Request<Body>), Error = Error> + 'static {
+    let problems = target.validate();
+    let valid = if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::InvalidTarget {
+            target: format!("{} {}", target.method(), target.path()),
+            problems: problems.join("; "),
+        })
+    };
+    let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(seed);
+
+    let requests = iter_ok::<_, Error>(0..niter)
+        // sample request URI and body, skipping invalid URIs of raw values
+        .filter_map(move |i| {
+            // take turns between base URLs
+            let base_url = base_urls[i as usize % base_urls.len()].clone();
+            match sample_request(&target, &base_url, i, &mut rng, &global_headers) {
+                Ok(spec) => Some((i, base_url, spec)),
+                Err(err) => {
+                    warn!("{:4} > {:?} {} skipped: {}", i, target.method(), target.path(), err);
+                    None
+                }
+            }
+        })
+        // build HTTP request, skipping those which cannot be built
+        .filter_map(|(i, base_url, spec)| match spec.build() {
+            Ok(req) => Some((i, base_url, spec, req)),
+            Err(err) => {
+                warn!("{:4} > {:?} {:?} skipped: {}", i, spec.method, spec.uri, err);
+                None
+            }
+        });
+    result(valid).map(move |()| requests).flatten_stream()
+}
+
+/// Obtain a stream of requests from a test target. Each item is a future
+/// resolving to the respective server outcome once the request is sent,
+/// so that requests can be performed concurrently.
+/// The outcomes can be reproduced by running the target again with the
+/// same seed.
+pub fn test_target_requests<C>(
+    client: Arc<Client<C>>,
+    base_urls: Arc<[String]>,
+    target: TestTarget,
+    niter: u32,
+    seed: u64,
+    options: RequestOptions,
+) -> impl Stream<Item = impl Future<Item = TargetOutcome, Error = Error>, Error = Error> + 'static
+where
+    C: Connect + 'static,
+{
+    let name = format!("{} {}", target.method(), target.path());
+
+    // not required, but prevents deep copying of the test target object
+    let target = Arc::from(target);
+
+    let delay = options.delay;
+    let deadline = options.deadline;
+    let name_check = name.clone();
+    // the time until which the target is paused after a rate limited response
+    let paused_until = Arc::new(Mutex::new(None::<Instant>));
+    let pause = paused_until.clone();
+    let warmup = options.warmup;
+    // warm-up requests are sampled with a seed of their own, so that the
+    // tested requests are numbered from 0 and the same with or without them
+    let headers = options.headers.clone();
+    let warmups = sample_requests(base_urls.clone(), target.clone(), warmup, !seed, headers.clone());
+    let tested = sample_requests(base_urls, target.clone(), niter, seed, headers);
+    warmups
+        .map(|request| (true, request))
+        .chain(tested.map(|request| (false, request)))
+        // wait before all but the first request, and while paused
+        .and_then(move |(warming, request)| {
+            let now = Instant::now();
+            let first = request.0 == 0 && warming == (warmup > 0);
+            let delayed = delay.filter(|_| !first).map(|delay| now + delay);
+            let paused = paused_until.lock().unwrap().filter(|until| *until > now);
+            match delayed.max(paused) {
+                Some(until) => Either::A(
+                    Delay::new(until)
+                        .map_err(|err| Error::Timer { err })
+                        .map(move |_| (warming, request)),
+                ),
+                None => Either::B(result(Ok((warming, request)))),
+            }
+        })
+        .take_while(move |_| {
+            let running = deadline.is_none_or(|deadline| Instant::now() < deadline);
+            if !running {
+                info!("Maximum duration reached, no more requests to {}", name_check);
+            }
+            Ok(running)
+        })
+        // send request
+        .map(move |(warming, (i, base_url, spec, req))| {
+            let target = target.clone();
+            let name = name.clone();
+            let endpoint = target.path().into_owned();
+            let method = spec.method.clone();
+            let uri = spec.uri.clone();
+            let request = spec.clone();
+            let start = Instant::now();
+            let client = client.clone();
+            let max_redirects = options.max_redirects;
+            let timeout = options.timeout;
+            let retries = options.retries;
+            // send the request once, following redirects
+            let cookies = options.cookies.clone();
+            let framing = target.body_framing;
+            let transport = options.framing_transport.clone();
+            let capturing = target.clone();
+            let pause = pause.clone();
+            let rate_limit_ok = options.rate_limit_ok;
+            let ignore_5xx = options.ignore_5xx;
+            let attempt = move |req, spec: RequestSpec| {
+                let client = client.clone();
+                let cookies = cookies.clone();
+                let transport = transport.clone();
+                let response = loop_fn((req, spec, max_redirects), move |(mut req, spec, hops)| {
+                    let cookies = cookies.clone();
+                    if let Some(jar) = &cookies {
+                        jar.lock().unwrap().apply(&mut req);
+                    }
+                    let response = match spec.encoded_body() {
+                        // a separate connection sends the body with the requested framing
+                        Some(body) if framing != BodyFraming::Exact => {
+                            let raw = raw_request(&req, &body, framing, i);
+                            let mut builder = Client::builder();
+                            builder.keep_alive(false);
+                            Either::A(match &transport {
+                                Some(transport) => builder
+                                    .build(FramingConnector::new(transport.clone(), raw))
+                                    .request(req),
+                                None => builder
+                                    .build(FramingConnector::new(
+                                        HttpConnector::new_with_tokio_threadpool_resolver(),
+                                        raw,
+                                    ))
+                                    .request(req),
+                            })
+                        }
+                        _ => Either::B(client.request(req)),
+                    };
+                    response.map(move |r| {
+                        if let Some(jar) = &cookies {
+                            jar.lock().unwrap().store(&spec.uri, r.headers());
+                        }
+                        let status = r.status();
+                        if hops > 0 && status.is_redirection() {
+                            let next = r
+                                .headers()
+                                .get(LOCATION)
+                                .and_then(|location| location.to_str().ok())
+                                .and_then(|location| resolve_location(&spec.uri, location))
+                                .map(|location| spec.redirect(status, location));
+                            if let Some(next) = next {
+                                if let Ok(req) = next.build() {
+                                    info!("\t{} -> {:?} {:?}", status, next.method, next.uri);
+                                    return Loop::Continue((req, next, hops - 1));
+                                }
+                            }
+                        }
+                        Loop::Break(r)
+                    })
+                });
+                match timeout {
+                    Some(timeout) => Either::A(Timeout::new(response, timeout)),
+                    None => Either::B(response.map_err(TimeoutError::inner)),
+                }
+            };
+            // retry the request on connection errors, with exponential backoff
+            let response = loop_fn((req, 0), move |(req, tries)| {
+                let spec = spec.clone();
+                attempt(req, spec.clone()).then(move |r| match r {
+                    Err(e) if e.is_inner() => {
+                        let err = e.into_inner().unwrap();
+                        if err.is_connect() && tries < retries {
+                            let delay = RETRY_BASE_DELAY * 2u32.saturating_pow(tries);
+                            warn!(
+                                "{:?} {:?} -> {}, retrying in {:?}",
+                                spec.method, spec.uri, err, delay
+                            );
+                            // the request was built successfully before
+                            let req = spec.build().expect("request should be rebuilt");
+                            Either::A(
+                                Delay::new(Instant::now() + delay)
+                                    .map_err(TimeoutError::timer)
+                                    .map(move |_| Loop::Continue((req, tries + 1))),
+                            )
+                        } else {
+                            Either::B(result(Err(TimeoutError::inner(err))))
+                        }
+                    }
+                    r => Either::B(result(r.map(Loop::Break))),
+                })
+            });
+            response.then(move |r| match r {
+                Ok(r) => {
+                    let elapsed = start.elapsed();
+                    let elapsed_ms = elapsed.as_millis() as u64;
+                    // convert 5xx server responses to errors
+                    let (parts, body) = r.into_parts();
+                    let status = parts.status;
+                    let code = status.as_u16();
+                    if status == StatusCode::TOO_MANY_REQUESTS {
+                        if let Some(wait) = retry_after(&parts.headers) {
+                            let wait = wait.min(MAX_RETRY_AFTER);
+                            warn!("{:?} {:?} -> rate limited, pausing for {:?}", method, uri, wait);
+                            let until = Instant::now() + wait;
+                            let mut paused_until = pause.lock().unwrap();
+                            *paused_until = (*paused_until).max(Some(until));
+                        }
+                    }
+                    if status.is_server_error() && !ignore_5xx {
+                        warn!(
+                            method:% = method, uri:% = uri, status = code, elapsed_ms = elapsed_ms;
+                            "{:?} {:?} -> returned error {}", method, uri, status
+                        );
+                    } else if status.is_server_error() {
+                        info!(
+                            method:% = method, uri:% = uri, status = code, elapsed_ms = elapsed_ms;
+                            "Response: {} ({:?}), ignored", status, parts.version
+                        );
+                    } else if target.is_forbidden_status(status) {
+                        warn!(
+                            method:% = method, uri:% = uri, status = code, elapsed_ms = elapsed_ms;
+                            "{:?} {:?} -> returned forbidden {}", method, uri, status
+                        );
+                        return Ok(ServerOutcome::forbidden(
+                            method,
+                            uri,
+                            elapsed,
+                            status,
+                            parts.version,
+                            parts.headers,
+                            body,
+                        ));
+                    } else if target.is_unexpected_status(status)
+                        && !(rate_limit_ok && status == StatusCode::TOO_MANY_REQUESTS)
+                    {
+                        warn!(
+                            method:% = method, uri:% = uri, status = code, elapsed_ms = elapsed_ms;
+                            "{:?} {:?} -> returned unexpected {}", method, uri, status
+                        );
+                        return Ok(ServerOutcome::unexpected(
+                            method,
+                            uri,
+                            elapsed,
+                            status,
+                            parts.version,
+                            parts.headers,
+                            body,
+                        ));
+                    } else {
+                        info!(
+                            method:% = method, uri:% = uri, status = code, elapsed_ms = elapsed_ms;
+                            "Response: {} ({:?})", status, parts.version
+                        );
+                    }
+                    let outcome = ServerOutcome::with_status(
+                        method,
+                        uri,
+                        elapsed,
+                        status,
+                        parts.version,
+                        parts.headers,
+                        body,
+                    );
+                    if ignore_5xx {
+                        Ok(outcome.ignoring_server_error())
+                    } else {
+                        Ok(outcome)
+                    }
+                }
+                Err(e) if e.is_elapsed() => {
+                    let elapsed = start.elapsed();
+                    warn!(
+                        method:% = method, uri:% = uri, elapsed_ms = elapsed.as_millis() as u64;
+                        "{:?} {:?} -> timed out after {:?}", method, uri, elapsed
+                    );
+                    Ok(ServerOutcome::timeout(method, uri, elapsed))
+                }
+                Err(e) if e.is_timer() => Err(Error::Timer {
+                    err: e.into_timer().unwrap(),
+                }),
+                Err(e) => {
+                    let elapsed = start.elapsed();
+                    let err = e.into_inner().unwrap();
+                    if err.is_connect() {
+                        Err(Error::HttpStream { method, uri, err })
+                    } else {
+                        // errors that are the server's fault should stick to ServerOutcome
+                        Ok(ServerOutcome::bad_http(method, uri, elapsed, err))
+                    }
+                }
+            })
+            .and_then(move |outcome| inspect_body(capturing, outcome))
+            .map(move |outcome| TargetOutcome {
+                target: name,
+                endpoint,
+                base_url,
+                seed,
+                iteration: i,
+                warmup: warming,
+                request,
+                outcome,
+            })
+        })
+}
+
+/// Capture the values of a target from the body of a successful response,
+/// and check the body of a good response for the strings which it must
+/// (not) contain, keeping the body in the outcome.
+fn inspect_body(
+    target: Arc<TestTarget>,
+    outcome: ServerOutcome,
+) -> impl Future<Item = ServerOutcome, Error = Error> {
+    let ServerOutcome {
+        method,
+        uri,
+        elapsed,
+        kind,
+    } = outcome;
+    match kind {
+        OutcomeKind::Good {
+            status,
+            version,
+            headers,
+            body,
+        } if (target.checks_body() && method != Method::HEAD)
+            || (!target.capture.is_empty() && status.is_success()) =>
+        {
+            Either::A(body.concat2().then(move |body| match body {
+                Ok(body) => {
+                    // compressed bodies are inspected once decompressed,
+                    // and kept as they are otherwise
+                    let decoded = decoded_body(&headers, &body);
+                    if status.is_success() {
+                        for capture in &target.capture {
+                            if let Some(value) = capture.capture(&decoded) {
+                                info!("\tcaptured {} = {:?}", capture.name, value);
+                            }
+                        }
+                    }
+                    // HEAD responses have no body to check
+                    let failure = if method == Method::HEAD {
+                        None
+                    } else {
+                        target.check_body(&decoded)
+                    };
+                    let kind = match failure {
+                        Some(failure) => {
+                            warn!("{:?} {:?} -> {} {}", method, uri, status, failure);
+                            OutcomeKind::BadBody {
+                                status,
+                                version,
+                                headers,
+                                body: Body::from(body),
+                                failure,
+                            }
+                        }
+                        None => OutcomeKind::Good {
+                            status,
+                            version,
+                            headers,
+                            body: Body::from(body),
+                        },
+                    };
+                    Ok(ServerOutcome {
+                        method,
+                        uri,
+                        elapsed,
+                        kind,
+                    })
+                }
+                Err(err) => Ok(ServerOutcome::bad_http(method, uri, elapsed, err)),
+            }))
+        }
+        kind => Either::B(result(Ok(ServerOutcome {
+            method,
+            uri,
+            elapsed,
+            kind,
+        }))),
+    }
+}
+
+/// Run the given test targets, with up to `concurrency` requests in flight.
+/// Each target is tested `n` times, unless the target overrides it, and
+/// gets its own random seed, derived from the given seed and the target's
+/// position in the list.
+pub fn run_targets<C>(
+    client: Arc<Client<C>>,
+    base_urls: Arc<[String]>,
+    targets: Vec<TestTarget>,
+    n: u32,
+    seed: u64,
+    concurrency: usize,
+    options: RequestOptions,
+) -> impl Stream<Item = TargetOutcome, Error = Error> + 'static
+where
+    C: Connect + 'static,
+{
+    // targets using captured values wait until all requests to the targets
+    // before them are complete, so each of these starts a new stage
+    let mut stages: Vec<Vec<(usize, TestTarget)>> = Vec::new();
+    for (i, target) in targets.into_iter().enumerate() {
+        match stages.last_mut() {
+            Some(stage) if !target.uses_captures => stage.push((i, target)),
+            _ => stages.push(vec![(i, target)]),
+        }
+    }
+    iter_ok::<_, Error>(stages)
+        .map(move |stage| {
+            let client = client.clone();
+            let base_urls = base_urls.clone();
+            let options = options.clone();
+            iter_ok::<_, Error>(stage)
+                .map(move |(i, target)| {
+                    // each target gets its own deterministic generator
+                    let seed = seed.wrapping_add(i as u64);
+                    let n = target.iterations.unwrap_or(n);
+                    test_target_requests(
+                        client.clone(),
+                        base_urls.clone(),
+                        target,
+                        n,
+                        seed,
+                        options.clone(),
+                    )
+                })
+                .flatten()
+                .buffer_unordered(concurrency.max(1))
+        })
+        .flatten()
+}
+
+/// Run a test target against the given base URL `n` times,
+/// one request at a time and with the default request options.
+/// The stream fails with `Error::InvalidTarget` if the target has any problem.
+pub fn run_target(
+    target: TestTarget,
+    base_url: &str,
+    n: u32,
+    seed: u64,
+) -> impl Stream<Item = TargetOutcome, Error = Error> + 'static {
+    run_targets(
+        Arc::new(Client::new()),
+        Arc::from(vec![base_url.to_string()]),
+        vec![target],
+        n,
+        seed,
+        1,
+        RequestOptions::default(),
+    )
+}
+
+/// Run all test targets of a configuration against the given base URL
+/// `n` times each, one request at a time, with the default request options
+/// and a random seed. The seed of each target is kept in its outcomes.
+/// The stream fails with `Error::InvalidTarget` on the first target with
+/// any problem.
+pub fn run_config(
+    config: Config,
+    base_url: &str,
+    n: u32,
+) -> impl Stream<Item = TargetOutcome, Error = Error> + 'static {
+    run_targets(
+        Arc::new(Client::new()),
+        Arc::from(vec![base_url.to_string()]),
+        config.targets,
+        n,
+        rand::random(),
+        1,
+        RequestOptions::default(),
+    )
+}
@@ -1,39 +1,55 @@
 #![deny(unsafe_code)]
 
-use std::io::Error as IoError;
-use std::fs::{create_dir_all, File};
-use std::path::PathBuf;
-use std::sync::Arc;
-
-use env_logger;
-use failure::Fail;
-use futures::future::{ok, result};
+use std::env;
+use std::fs::create_dir_all;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Utc};
+use futures::future::{err, ok, result, Either};
+use glob::Pattern;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
 use futures::prelude::*;
 use futures::stream::iter_ok;
-use http::uri::{InvalidUri, Uri};
-use http::Error as HttpError;
-use hyper::error::Error as HyperError;
-use hyper::{Body, Client, Method, Request};
-use log::{info, warn, error};
+use http::uri::Uri;
+use http::header::{HeaderMap, AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE, USER_AGENT};
+use hyper::body::Payload;
+use hyper::client::connect::dns::GaiResolver;
+use hyper::client::HttpConnector;
+use hyper::client::connect::Connect;
+use hyper::{Body, Client, Method, Request, StatusCode};
+use hyper_proxy::{Intercept, Proxy, ProxyConnector};
+use hyper_tls::HttpsConnector;
+use native_tls::{Certificate, TlsConnector};
+use log::kv::{Error as KvError, Key, Value as LogValue, VisitSource};
+use log::{info, error, LevelFilter, Record};
+use serde_json::{Map as JsonMap, Value as JsonValue};
 use structopt::StructOpt;
 use tokio::runtime::Runtime;
-use tokio_io::AsyncWrite;
+use tokio::timer::Timeout;
+use tokio_io::io::write_all;
 
-mod config;
-use config::Config;
-mod outcome;
-use outcome::*;
-mod target;
-use target::*;
+use heel_gun::config::{ConfigFormat, DynError};
+use heel_gun::outcome::*;
+use heel_gun::report::*;
+use heel_gun::request::*;
+use heel_gun::shrink::{shrink_request, FailureSignature};
+use heel_gun::target;
+use heel_gun::{run_targets, sample_requests, Config, Error, RequestOptions};
 
 /// Test for HTTP server robustness
 #[derive(Debug, StructOpt)]
 pub struct HeelGun {
-    /// the base URL to test
+    /// the base URL to test (or a comma-separated list of base URLs, used in turn)
     url: String,
-    /// path to configuration file
-    #[structopt(parse(from_os_str))]
-    config: PathBuf,
+    /// path to configuration file (or `-` to read it from the standard input)
+    #[structopt(parse(from_os_str), required_unless = "replay")]
+    config: Option<PathBuf>,
     /// number of iterations to test for each target
     #[structopt(short = "N", default_value = "100")]
     n: u32,
@@ -43,169 +59,553 @@ pub struct HeelGun {
     /// Ignore stateful HTTP methods
     #[structopt(long = "get-only")]
     get_only: bool,
+    /// Seed for the random request generators, for reproducible runs
+    #[structopt(long = "seed")]
+    seed: Option<u64>,
+    /// Maximum time to wait for each response, in seconds
+    #[structopt(long = "timeout")]
+    timeout: Option<u64>,
+    /// Maximum number of requests in flight at once
+    #[structopt(long = "concurrency", default_value = "1")]
+    concurrency: usize,
+    /// Format of the failure log (csv, or jsonl which also records the good outcomes)
+    #[structopt(long = "format", default_value = "csv")]
+    format: OutputFormat,
+    /// Maximum number of redirects to follow for each request
+    #[structopt(long = "follow-redirects", default_value = "0")]
+    follow_redirects: u32,
+    /// Credentials for all requests, as `basic:<user>:<password>` or `bearer:<token>`
+    #[structopt(long = "auth")]
+    auth: Option<Auth>,
+    /// Maximum number of times to retry a request after a connection error
+    #[structopt(long = "retries", default_value = "0")]
+    retries: u32,
+    /// Print the requests which would be sent, without sending them
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+    /// URL of an HTTP proxy to send all requests through
+    #[structopt(long = "proxy", env = "HTTP_PROXY")]
+    proxy: Option<Uri>,
+    /// Do not verify the certificates of HTTPS servers
+    #[structopt(long = "insecure")]
+    insecure: bool,
+    /// Path to a PEM file with an additional CA certificate to trust
+    #[structopt(long = "ca-cert", parse(from_os_str))]
+    ca_cert: Option<PathBuf>,
+    /// Also save the responses which are not failures, under `good` in the output directory
+    #[structopt(long = "save-all")]
+    save_all: bool,
+    /// Maximum number of failures tolerated before exiting with an error code
+    #[structopt(long = "fail-threshold", default_value = "0")]
+    fail_threshold: u32,
+    /// Keep the cookies set by the server and send them in subsequent requests
+    #[structopt(long = "cookies")]
+    cookies: bool,
+    /// Only test the targets with an endpoint matching one of these glob patterns
+    #[structopt(long = "include", number_of_values = 1)]
+    include: Vec<Pattern>,
+    /// Do not test the targets with an endpoint matching any of these glob patterns
+    #[structopt(long = "exclude", number_of_values = 1)]
+    exclude: Vec<Pattern>,
+    /// Collapse the failures with the same method, URI template and status into a single record
+    #[structopt(long = "dedup")]
+    dedup: bool,
+    /// Time to wait between consecutive requests to the same target, in milliseconds
+    #[structopt(long = "delay")]
+    delay: Option<u64>,
+    /// Write the results directly in the output directory,
+    /// instead of a new subdirectory named after the time of the run
+    #[structopt(long = "no-timestamp")]
+    no_timestamp: bool,
+    /// Do not check that the server can be reached before testing it
+    #[structopt(long = "skip-precheck")]
+    skip_precheck: bool,
+    /// Send the requests recorded in a CSV failure log again, instead of
+    /// sampling requests from a configuration
+    #[structopt(long = "replay", parse(from_os_str))]
+    replay: Option<PathBuf>,
+    /// The `User-Agent` header of all requests
+    #[structopt(long = "user-agent", default_value = DEFAULT_USER_AGENT)]
+    user_agent: String,
+    /// Do not count rate limited responses (429) as failures,
+    /// even if the target does not expect them
+    #[structopt(long = "rate-limit-ok")]
+    rate_limit_ok: bool,
+    /// Do not count server errors (5xx) as failures,
+    /// only connection failures and timeouts
+    #[structopt(long = "ignore-5xx")]
+    ignore_5xx: bool,
+    /// Do not show the progress bar
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+    /// Log more details, up to `-vvvv` (warnings, requests, debugging and
+    /// tracing); `RUST_LOG` takes precedence if set
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: u8,
+    /// Format of the log output (text, or json for one JSON object per line)
+    #[structopt(long = "log-format", default_value = "text")]
+    log_format: LogFormat,
+    /// Only count the outcomes and print the summary,
+    /// without writing anything to the output directory
+    #[structopt(long = "count-only")]
+    count_only: bool,
+    /// Save the response bodies as sequentially numbered files under
+    /// `bodies`, listed in `manifest.csv`, instead of after the request URI
+    #[structopt(long = "flat-bodies")]
+    flat_bodies: bool,
+    /// Record each failing request as a `curl` command in the failure log,
+    /// for reproducing it
+    #[structopt(long = "emit-curl")]
+    emit_curl: bool,
+    /// Maximum number of idle connections to keep open for each host,
+    /// for reuse by later requests
+    #[structopt(long = "pool-max-idle")]
+    pool_max_idle: Option<usize>,
+    /// Open a new connection for each request, instead of reusing connections
+    #[structopt(long = "no-keepalive")]
+    no_keepalive: bool,
+    /// Look for a smaller request which fails the same way as each failing
+    /// request, and record it in the failure log
+    #[structopt(long = "shrink")]
+    shrink: bool,
+    /// Format of the configuration file (json, yaml, toml, routes or txt),
+    /// instead of guessing it from the file name
+    #[structopt(long = "config-format")]
+    config_format: Option<ConfigFormat>,
+    /// File of `Name: Value` lines with HTTP headers to include in all
+    /// requests, before the headers of each target
+    #[structopt(long = "headers-file", parse(from_os_str))]
+    headers_file: Option<PathBuf>,
+    /// Test all targets again and again, each time with a new seed,
+    /// until interrupted
+    #[structopt(long = "loop")]
+    looping: bool,
+    /// Test all targets in a loop (as with `--loop`) for this time, in seconds
+    #[structopt(long = "loop-for")]
+    loop_for: Option<u64>,
+    /// Comma-separated columns of the CSV failure log, in order, such as
+    /// `method,uri,status,elapsed,iteration,target`
+    #[structopt(long = "csv-columns", use_delimiter = true)]
+    csv_columns: Vec<CsvColumn>,
+    /// Stop sending requests after this time, in seconds
+    #[structopt(long = "max-duration")]
+    max_duration: Option<u64>,
+    /// Maximum number of bytes of each response body to save
+    #[structopt(long = "max-body-bytes")]
+    max_body_bytes: Option<u64>,
+    /// Number of requests to send to each target before testing it,
+    /// without counting their outcomes
+    #[structopt(long = "warmup", default_value = "0")]
+    warmup: u32,
+    /// Connect to the given address for a host name instead of resolving it,
+    /// as `host:address` (the `Host` header is kept)
+    #[structopt(long = "resolve", number_of_values = 1)]
+    resolve: Vec<ResolveOverride>,
+    /// Send requests with HTTP/2 only, assuming the server supports it (prior knowledge)
+    #[structopt(long = "http2")]
+    http2: bool,
 }
 
-/// Errors obtained from target testing
-#[derive(Debug, Fail)]
-pub enum Error {
-    /// some other error occurred while handling the HTTP stream
-    #[fail(display = "HTTP stream error: {}", err)]
-    HttpStream {
-        /// the HTTP method of the request performed
-        method: Method,
-        /// the URI of the HTTP request performed
-        uri: Uri,
-        err: HyperError,
-    },
-    /// some other error occurred while building HTTP content
-    #[fail(display = "HTTP error: {}", err)]
-    Http {
-        /// the HTTP method of the request performed
-        method: Method,
-        /// the URI of the HTTP request performed
-        uri: Uri,
-        err: HttpError,
-    },
-    /// some error occurred while fetching a response body
-    #[fail(display = "Hyper error: {}", err)]
-    Hyper {
-        err: HyperError,
-    },
-    /// some error occurred while doing disk I/O 
-    #[fail(display = "I/O error: {}", err)]
-    Io {
-        err: std::io::Error,
-    },
-    /// the target test sampler produced an illegal URI
-    #[fail(display = "Invalid request URI: {}", err)]
-    InvalidRequest {
-        #[fail(cause)]
-        err: InvalidUri,
-    },
-    /// could not write a failure entry to disk
-    #[fail(display = "Failed to write outcome: {}", err)]
-    WriteFailure {
-        #[fail(cause)]
-        err: csv::Error,
-    },
-}
-
-impl From<InvalidUri> for Error {
-    fn from(err: InvalidUri) -> Self {
-        Error::InvalidRequest { err }
+/// Build the TLS connector for HTTPS requests.
+fn tls_connector(insecure: bool, ca_cert: Option<&PathBuf>) -> Result<TlsConnector, DynError> {
+    let mut builder = TlsConnector::builder();
+    if insecure {
+        eprintln!("Warning: TLS certificate verification is disabled");
+        builder.danger_accept_invalid_certs(true);
+    }
+    if let Some(path) = ca_cert {
+        let pem = std::fs::read(path)
+            .map_err(|e| format!("could not read CA certificate '{}': {}", path.display(), e))?;
+        builder.add_root_certificate(Certificate::from_pem(&pem)?);
     }
+    Ok(builder.build()?)
 }
 
-impl From<IoError> for Error {
-    fn from(err: IoError) -> Self {
-        Error::Io { err }
+/// Choose a new subdirectory of the output directory for this run,
+/// named after the current time (in UTC).
+fn run_dir(outdir: &Path) -> PathBuf {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let name = DateTime::from_timestamp(now, 0)
+        .unwrap_or_default()
+        .format("%Y-%m-%dT%H-%M-%S")
+        .to_string();
+    // do not mix up with a run started in the same second
+    let mut dir = outdir.join(&name);
+    let mut suffix = 1;
+    while dir.exists() {
+        dir = outdir.join(format!("{}_{}", name, suffix));
+        suffix += 1;
     }
+    dir
 }
 
-impl From<HyperError> for Error {
-    fn from(err: HyperError) -> Self {
-        Error::Hyper { err }
-    }
+/// Create the progress bar of a run with the given number of requests,
+/// if known.
+fn progress_bar(total: Option<u64>) -> ProgressBar {
+    let bar = ProgressBar::with_draw_target(total, ProgressDrawTarget::stdout());
+    let template = match total {
+        Some(_) => "{elapsed_precise} [{bar:40}] {pos}/{len} requests (eta {eta})",
+        None => "{elapsed_precise} {pos} requests",
+    };
+    let style = ProgressStyle::with_template(template)
+        .unwrap()
+        .progress_chars("=> ");
+    bar.set_style(style);
+    bar
 }
 
-impl From<csv::Error> for Error {
-    fn from(err: csv::Error) -> Self {
-        Error::WriteFailure { err }
-    }
+/// The seed of the given cycle of a run in a loop, which is the run's seed
+/// in the first cycle
+fn cycle_seed(seed: u64, cycle: u64) -> u64 {
+    // far apart from the seeds of the targets, which are consecutive
+    seed ^ cycle.wrapping_mul(0x9E37_79B9_7F4A_7C15)
 }
 
-/// Obtain a stream of requests and respective responses from a test target.
-fn test_target_requests<C: 'static, U: 'static>(
-    client: Arc<Client<C>>,
-    base_url: U,
-    target: TestTarget,
-    niter: u32,
-) -> impl Stream<Item = ServerOutcome, Error = Error> + 'static
+/// Check that the server behind the given base URL can be reached,
+/// by sending it a `HEAD` request. Any response will do.
+fn precheck<C>(
+    client: &Client<C>,
+    base_url: &str,
+    timeout: Option<Duration>,
+) -> impl Future<Item = (), Error = DynError>
 where
-    C: hyper::client::connect::Connect,
-    U: AsRef<str>,
+    C: Connect + Sync + 'static,
 {
-    let mut rng = rand_pcg::Pcg64Mcg::new(rand::random());
-
-    // not required, but prevents deep copying of the test target object
-    let target = Arc::from(target);
-
-    iter_ok::<_, Error>(0..niter)
-        // sample request URI
-        .and_then(move |i| {
-            result(
-                target
-                    .sample(base_url.as_ref(), &mut rng)
-                    .map(|uri| {
-                        info!("{:4} > {:?} {:?}", i, target.method(), uri);
-                        (i, target.clone(), uri)
-                    })
-                    .map_err(|e| e.into()),
-            )
+    let response = match Request::head(base_url).body(Body::empty()) {
+        Ok(request) => Either::A(client.request(request).map(|_| ()).map_err(DynError::from)),
+        Err(e) => Either::B(err(DynError::from(e))),
+    };
+    let timeout = timeout.unwrap_or(PRECHECK_TIMEOUT);
+    Timeout::new(response, timeout).map_err(move |e| {
+        if e.is_elapsed() {
+            format!("no response after {} seconds", timeout.as_secs()).into()
+        } else if e.is_inner() {
+            e.into_inner().unwrap()
+        } else {
+            DynError::from(e.into_timer().unwrap())
+        }
+    })
+}
+
+/// Save a response body to the given file, with the response headers
+/// in a sibling file of the same name with the `.headers` extension.
+/// Only the first `max_bytes` bytes of the body are saved, if given,
+/// in which case the truncation is noted in the headers file.
+/// Resolves to the full size of the body, unless it could not be saved.
+fn save_response(
+    body_path: PathBuf,
+    headers: HeaderMap,
+    body: Body,
+    max_bytes: Option<u64>,
+) -> impl Future<Item = Option<u64>, Error = ()> {
+    let body_path_parent = body_path.parent().unwrap().to_owned();
+    let mut headers_path = body_path.clone().into_os_string();
+    headers_path.push(".headers");
+    info!("\tSaving response body to {}", body_path.display());
+    tokio_fs::create_dir_all(body_path_parent)
+        .and_then(|_| tokio_fs::File::create(body_path))
+        .map_err(Error::from)
+        .and_then(move |file| {
+            body.map_err(Error::from).fold((file, 0u64), move |(file, size), chunk| {
+                let len = chunk.len() as u64;
+                let keep = max_bytes.map_or(len, |max| max.saturating_sub(size).min(len));
+                let write = if keep > 0 {
+                    let bytes = chunk.into_bytes().slice_to(keep as usize);
+                    Either::A(write_all(file, bytes).map(|(file, _)| file).map_err(Error::from))
+                } else {
+                    Either::B(ok(file))
+                };
+                write.map(move |file| (file, size + len))
+            })
         })
-        // build HTTP request
-        .and_then(move |(i, target, uri)| {
-            let method = target.method();
-            result(
-                match Request::builder()
-                    .method(target.method())
-                    .uri(&uri)
-                    .body(Body::empty())
-                {
-                    Ok(req) => Ok((i, target, uri, req)),
-                    Err(err) => Err(Error::Http { method, uri, err }),
-                },
-            )
+        .and_then(move |(_, size)| {
+            let mut block = header_block(&headers);
+            if let Some(max) = max_bytes.filter(|max| size > *max) {
+                let note = format!("x-heel-gun-truncated: saved {} of {} bytes\r\n", max, size);
+                block.extend_from_slice(note.as_bytes());
+            }
+            tokio_fs::write(headers_path, block)
+                .map(move |_| Some(size))
+                .map_err(Error::from)
         })
-        // send request
-        .and_then(move |(_i, target, uri, req)| {
-            let method = target.method();
-            client.request(req).then(move |r| match r {
-                Ok(r) => {
-                    // convert 5xx server responses to errors
-                    let status = r.status();
-                    let body = r.into_body();
-                    if status.is_server_error() {
-                        warn!("{:?} {:?} -> returned error {}", method, uri, status);
-                    } else {
-                        info!("Response: {}", status);
-                    }
-                    Ok(ServerOutcome::with_status(method, uri, status, body))
-                }
-                Err(err) => {
-                    if err.is_connect() {
-                        Err(Error::HttpStream { method, uri, err })
-                    } else {
-                        // errors that are the server's fault should stick to ServerOutcome
-                        Ok(ServerOutcome::bad_http(method, uri, err))
-                    }
-                }
-            })
+        .or_else(|e| {
+            error!("Could not save response: {}", e);
+            Ok(None)
         })
 }
 
-#[cfg(target_os = "windows")]
-fn to_filename(path_and_query: String) -> String {
-    // replace `?` (reserved character in some systems)
-    path_and_query.replace('?', "-Q-")
+/// Render response headers as they would appear in the HTTP response,
+/// one `name: value` line each. Values are kept as raw bytes, since they
+/// are not guaranteed to be valid UTF-8.
+fn header_block(headers: &HeaderMap) -> Vec<u8> {
+    let mut block = Vec::new();
+    for (name, value) in headers {
+        block.extend_from_slice(name.as_str().as_bytes());
+        block.extend_from_slice(b": ");
+        block.extend_from_slice(value.as_bytes());
+        block.extend_from_slice(b"\r\n");
+    }
+    block
+}
+
+/// Print a request in a human readable form, roughly as it would be sent.
+fn print_request(spec: &RequestSpec) {
+    println!("{} {}", spec.method, spec.uri);
+    for (name, value) in &spec.headers {
+        println!("\t{}: {}", name, value);
+    }
+    if let Some(body) = &spec.body {
+        println!("\t{}: {}", CONTENT_TYPE, spec.content_type);
+        if let Some(encoding) = spec.compress {
+            println!("\t{}: {}", CONTENT_ENCODING, encoding.name());
+        }
+        println!("\t{}", body);
+    }
+}
+
+/// Build the path of the file (relative to the output directory) for saving
+/// a response body under the given unique number, with `--flat-bodies`.
+fn flat_body_path(id: u64, extension: &str) -> String {
+    format!("bodies/{:06}.{}", id, extension)
+}
+
+/// The file extension for saving a response body with the given headers,
+/// by its content type. Compressed bodies and bodies of other types
+/// are saved as `.bin` files.
+fn body_extension(headers: &HeaderMap) -> &'static str {
+    let compressed = headers
+        .get(CONTENT_ENCODING)
+        .is_some_and(|encoding| encoding.as_bytes() != b"identity");
+    let content_type = match headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()) {
+        Some(content_type) if !compressed => content_type,
+        _ => return "bin",
+    };
+    let media_type = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    match media_type.as_str() {
+        "application/json" => "json",
+        "text/html" | "application/xhtml+xml" => "html",
+        "application/xml" | "text/xml" => "xml",
+        "text/plain" => "txt",
+        t if t.ends_with("+json") => "json",
+        t if t.ends_with("+xml") => "xml",
+        _ => "bin",
+    }
+}
+
+/// Maximum length of a single component of a body file path
+const MAX_FILE_NAME_LEN: usize = 200;
+
+/// The `User-Agent` header of all requests, unless another one is given
+const DEFAULT_USER_AGENT: &str = concat!("heel-gun/", env!("CARGO_PKG_VERSION"));
+
+/// Time to wait for the server to respond to the pre-check,
+/// unless a timeout is given
+const PRECHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Build the path of the file (relative to the output directory) for
+/// saving a response body. Each component of the request URI is encoded so
+/// that the file cannot escape the output directory, and the given unique
+/// number is appended so that repeated requests do not overwrite each other.
+fn body_file_path(method: &Method, uri: &Uri, id: u64) -> String {
+    let mut components: Vec<String> = uri
+        .path()
+        .split('/')
+        .filter(|c| !c.is_empty())
+        .map(to_filename)
+        .collect();
+    let mut last = components.pop().unwrap_or_else(|| "_".to_string());
+    if let Some(query) = uri.query() {
+        last.push_str(&to_filename(&format!("?{}", query)));
+    }
+
+    let mut path = method.to_string();
+    for component in components {
+        path.push('/');
+        path.push_str(&shorten_filename(component));
+    }
+    path.push('/');
+    path.push_str(&format!("{}_{}", shorten_filename(last), id));
+    path
+}
+
+/// Encode a URI component into a portable file name. Only ASCII
+/// alphanumeric characters, `-`, `_` and `.` are kept, and a component made
+/// only of dots (such as `..`) is fully encoded.
+fn to_filename(component: &str) -> String {
+    let only_dots = component.bytes().all(|b| b == b'.');
+    let mut out = String::with_capacity(component.len());
+    for b in component.bytes() {
+        match b {
+            b'.' if !only_dots => out.push('.'),
+            b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'-' | b'_' => out.push(b as char),
+            b => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+/// Keep file names within a reasonable length,
+/// replacing the excess with a hash of the full name.
+fn shorten_filename(name: String) -> String {
+    if name.len() <= MAX_FILE_NAME_LEN {
+        return name;
+    }
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    // the name is ASCII, so any index is a character boundary
+    format!("{}-{:016x}", &name[..MAX_FILE_NAME_LEN - 17], hasher.finish())
+}
+
+/// The format of the log output
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum LogFormat {
+    /// human readable lines
+    Text,
+    /// one JSON object per line, with the fields of each event
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("Invalid log format: must be text or json"),
+        }
+    }
+}
+
+/// Set up logging with the level of the given verbosity,
+/// unless it is configured through the environment.
+fn init_logger(verbose: u8, format: LogFormat) {
+    let mut builder = if env::var_os("RUST_LOG").is_some() {
+        env_logger::Builder::from_default_env()
+    } else {
+        let level = match verbose {
+            0 => LevelFilter::Error,
+            1 => LevelFilter::Warn,
+            2 => LevelFilter::Info,
+            3 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        };
+        let mut builder = env_logger::Builder::new();
+        builder.filter_level(level);
+        builder
+    };
+    if format == LogFormat::Json {
+        builder.format(write_json_record);
+    }
+    builder.init();
 }
 
-#[cfg(not(target_os = "windows"))]
-fn to_filename(path_and_query: String) -> String {
-    path_and_query
+/// Write a log record as a JSON object, with the time, level, target and
+/// message of the record, followed by its fields (such as the method, URI,
+/// status and elapsed time of a response).
+fn write_json_record(buf: &mut env_logger::fmt::Formatter, record: &Record) -> io::Result<()> {
+    let mut object = JsonMap::new();
+    object.insert("time".into(), Utc::now().to_rfc3339().into());
+    object.insert("level".into(), record.level().as_str().into());
+    object.insert("target".into(), record.target().into());
+    object.insert("message".into(), record.args().to_string().into());
+    let mut fields = JsonFields(&mut object);
+    // the fields are only collected in memory
+    let _ = record.key_values().visit(&mut fields);
+    writeln!(buf, "{}", JsonValue::Object(object))
+}
+
+/// Collects the fields of a log record into a JSON object
+struct JsonFields<'a>(&'a mut JsonMap<String, JsonValue>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: LogValue<'kvs>) -> Result<(), KvError> {
+        let value = if let Some(n) = value.to_u64() {
+            n.into()
+        } else if let Some(n) = value.to_i64() {
+            n.into()
+        } else if let Some(b) = value.to_bool() {
+            b.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
 }
 
 fn main() {
-    env_logger::init();
     let HeelGun {
         config: config_file,
         n,
         url,
         outdir,
         get_only,
+        seed,
+        timeout,
+        concurrency,
+        format,
+        follow_redirects,
+        auth,
+        retries,
+        dry_run,
+        proxy,
+        insecure,
+        ca_cert,
+        save_all,
+        fail_threshold,
+        cookies,
+        include,
+        exclude,
+        http2,
+        dedup,
+        delay,
+        no_timestamp,
+        skip_precheck,
+        replay,
+        user_agent,
+        rate_limit_ok,
+        max_duration,
+        max_body_bytes,
+        warmup,
+        resolve,
+        ignore_5xx,
+        quiet,
+        verbose,
+        log_format,
+        count_only,
+        flat_bodies,
+        emit_curl,
+        pool_max_idle,
+        no_keepalive,
+        shrink,
+        config_format,
+        headers_file,
+        looping,
+        loop_for,
+        csv_columns,
     } = HeelGun::from_args();
+    init_logger(verbose, log_format);
 
-    let Config { targets } = Config::from_file(config_file).unwrap();
+    let (file, config) = match (&replay, &config_file) {
+        (Some(_), Some(_)) => {
+            eprintln!("No configuration file is used with --replay");
+            std::process::exit(1);
+        }
+        (Some(replay), None) => (replay, Config::from_failure_log(replay)),
+        (None, Some(config_file)) => (config_file, Config::from_file(config_file, config_format)),
+        (None, None) => unreachable!("a configuration file is required without --replay"),
+    };
+    let Config { targets, .. } = match config {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Could not load {}: {}", file.display(), e);
+            std::process::exit(1);
+        }
+    };
 
     // filter modifying methods
     let targets = if get_only {
@@ -214,68 +614,387 @@ fn main() {
         targets
     };
 
-    create_dir_all(&outdir).unwrap();
+    // filter by endpoint, with a leading slash for matching
+    let targets: Vec<_> = targets
+        .into_iter()
+        .filter(|t| {
+            let endpoint = format!("/{}", t.path().trim_start_matches('/'));
+            (include.is_empty() || include.iter().any(|p| p.matches(&endpoint)))
+                && !exclude.iter().any(|p| p.matches(&endpoint))
+        })
+        .collect();
+
+    let mut headers: Vec<_> = std::iter::once((USER_AGENT.to_string(), user_agent))
+        .chain(auth.iter().map(|auth| (AUTHORIZATION.to_string(), auth.header_value())))
+        .collect();
+    if let Some(path) = &headers_file {
+        let file_headers = match read_headers_file(path) {
+            Ok(file_headers) => file_headers,
+            Err(e) => {
+                eprintln!("Could not load {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        };
+        // the headers in the file replace those of the other options
+        headers.retain(|(name, _)| !file_headers.iter().any(|(n, _)| n.eq_ignore_ascii_case(name)));
+        headers.extend(file_headers);
+    }
 
-    let client = Arc::new(Client::new());
+    let mut options = RequestOptions {
+        timeout: timeout.map(Duration::from_secs),
+        max_redirects: follow_redirects,
+        headers,
+        retries,
+        cookies: if cookies { Some(Default::default()) } else { None },
+        delay: delay.map(Duration::from_millis),
+        rate_limit_ok,
+        ignore_5xx,
+        deadline: None,
+        warmup,
+        framing_transport: None,
+    };
+    let seed = seed.unwrap_or_else(rand::random);
+    println!("Using seed {}", seed);
+
+    let base_urls: Arc<[String]> = url
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(String::from)
+        .collect();
+    if base_urls.is_empty() {
+        eprintln!("No base URL given");
+        std::process::exit(1);
+    }
+
+    if dry_run {
+        for (i, target) in targets.into_iter().enumerate() {
+            let seed = seed.wrapping_add(i as u64);
+            let n = target.iterations.unwrap_or(n);
+            let requests =
+                sample_requests(base_urls.clone(), Arc::new(target), n, seed, options.headers.clone());
+            for request in requests.wait() {
+                match request {
+                    Ok((_, _, spec, _)) => print_request(&spec),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        break;
+                    }
+                }
+            }
+        }
+        return;
+    }
+
+    let tls = match tls_connector(insecure, ca_cert.as_ref()) {
+        Ok(tls) => tls,
+        Err(e) => {
+            eprintln!("Could not set up TLS: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let resolver = OverrideResolver::new(resolve, GaiResolver::new(4));
+    let mut http = HttpConnector::new_with_resolver(resolver);
+    http.enforce_http(false);
+    options.framing_transport = Some(HttpsConnector::from((http.clone(), tls.clone())));
+    let mut connector = ProxyConnector::unsecured(HttpsConnector::from((http, tls.clone())));
+    // HTTPS requests are tunneled through the proxy
+    connector.set_tls(Some(tls));
+    if let Some(proxy) = proxy {
+        info!("Sending requests through proxy {}", proxy);
+        connector.add_proxy(Proxy::new(Intercept::All, proxy));
+    }
+    let mut builder = Client::builder();
+    builder.http2_only(http2).keep_alive(!no_keepalive);
+    if let Some(max_idle) = pool_max_idle {
+        builder.max_idle_per_host(max_idle);
+    }
+    if no_keepalive {
+        println!("Connections: a new connection for each request");
+    } else {
+        match pool_max_idle {
+            Some(max_idle) => println!("Connections: kept alive, up to {} idle per host", max_idle),
+            None => println!("Connections: kept alive, with no limit of idle connections"),
+        }
+    }
+    let client = Arc::new(builder.build(connector));
 
     let mut runtime = Runtime::new().unwrap();
-    let output_filename = outdir.join("failures.csv");
-    let failures = File::create(&output_filename).unwrap();
-    let mut failures = csv::Writer::from_writer(failures);
-    failures.write_record(&["method", "uri", "reason", "file"]).unwrap();
+    if !skip_precheck {
+        for base_url in base_urls.iter() {
+            let probe = precheck(&client, base_url, timeout.map(Duration::from_secs));
+            if let Err(e) = runtime.block_on(probe) {
+                eprintln!("Could not reach {}: {}", base_url, e);
+                eprintln!("Check the URL, or pass --skip-precheck to test it anyway.");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let outdir = if no_timestamp { outdir } else { run_dir(&outdir) };
+    let output_filename = outdir.join(format.file_name());
+    // nothing is written to disk when only counting the outcomes
+    let failures = if count_only {
+        None
+    } else {
+        create_dir_all(&outdir).unwrap();
+        let mut columns = if csv_columns.is_empty() {
+            DEFAULT_CSV_COLUMNS.to_vec()
+        } else {
+            csv_columns
+        };
+        // the columns of the other options follow, unless chosen already
+        let mut optional = Vec::new();
+        if emit_curl {
+            optional.push(CsvColumn::Curl);
+        }
+        if shrink {
+            optional.extend(&[CsvColumn::ShrunkUri, CsvColumn::ShrunkBody]);
+        }
+        for column in optional {
+            if !columns.contains(&column) {
+                columns.push(column);
+            }
+        }
+        let failures = FailureWriter::create(&output_filename, format, columns).unwrap();
+        Some(Arc::new(Mutex::new(FailureLog::new(failures, dedup))))
+    };
+    let failure_recorder = failures.clone();
+    let manifest = if flat_bodies && !count_only {
+        let manifest = BodyManifest::create(outdir.join("manifest.csv")).unwrap();
+        Some(Arc::new(Mutex::new(manifest)))
+    } else {
+        None
+    };
+    let manifest_recorder = manifest.clone();
+    let summary = Arc::new(Mutex::new(Summary::default()));
+    let mut body_count = 0;
+    let summary_recorder = summary.clone();
     let executor = runtime.executor();
-    runtime.block_on(
-            iter_ok::<_, Error>(targets)
-                .map(move |target| test_target_requests(client.clone(), url.to_string(), target, n))
-                .flatten()
-                // write errors to failure record
-                .and_then(move |outcome| match outcome.kind {
-                    OutcomeKind::BadError { status, body } => {
-                        let method = outcome.method.to_string();
-                        let uri = outcome.uri.to_string();
-                        let reason = status.to_string();
-
-                        // write body to independent file
-                        let trimmed_uri = outcome.uri.path_and_query().unwrap().to_string();
-                        let trimmed_uri = to_filename(trimmed_uri);
-                        let relative_path = format!("{}{}", method, trimmed_uri);
-                        let body_path = outdir.join(&relative_path);
-                        let body_path_parent = body_path.parent().unwrap().to_owned();
-                        info!("\tSaving response body to {}", body_path.display());
-                        let report_file = tokio_fs::create_dir_all(body_path_parent)
-                            .and_then(|_| tokio_fs::File::create(body_path))
-                            .map_err(Error::from)
-                            .and_then(move |mut file| {
-                                body.map_err(Error::from)
-                                    .for_each(move |chunk| {
-                                        result(file.poll_write(&chunk).map(|_|()).map_err(Error::from))
-                                    })
-                            }).map_err(|e| {
-                                error!("Could not save response: {}", e);
-                                ()
-                            });
-                        executor.spawn(report_file);
-                        //runtime.spawn(report_file.map_err(|_|()));
-
-                        result(
-                            // write record to CSV file
-                            failures
-                                .write_record(&[&method, &uri, &reason, &relative_path])
-                                .map_err(|e| e.into()),
-                        )
-                    }
-                    OutcomeKind::BadHttp { err } => {
-                        let method = outcome.method.to_string();
-                        let uri = outcome.uri.to_string();
-                        let reason = err.to_string();
-
-                        result(
-                            failures
-                                .write_record(&[&method, &uri, &reason, "<N/A>"])
-                                .map_err(|e| e.into()),
-                        )
-                    }
-                    OutcomeKind::Good { .. } => ok(()),
+    // stop sending requests on Ctrl-C, keeping the outcomes so far;
+    // a final `None` marks the end of the run either way
+    let interrupt = tokio_signal::ctrl_c()
+        .flatten_stream()
+        .map(|_| {
+            eprintln!("Interrupted, stopping the test...");
+            None
+        })
+        .map_err(Error::from);
+    let looping = looping || loop_for.is_some();
+    let max_duration = match (max_duration, loop_for) {
+        (Some(max), Some(secs)) => Some(max.min(secs)),
+        (max, secs) => max.or(secs),
+    };
+    let deadline = max_duration.map(|secs| Instant::now() + Duration::from_secs(secs));
+    options.deadline = deadline;
+    let progress = if quiet || !std::io::stdout().is_terminal() {
+        ProgressBar::hidden()
+    } else if looping {
+        // there is no telling how many requests a loop sends
+        progress_bar(None)
+    } else {
+        let total = targets.iter().map(|t| u64::from(t.iterations.unwrap_or(n))).sum();
+        progress_bar(Some(total))
+    };
+    let progress_recorder = progress.clone();
+    // failing requests are shrunk with the same client, one at a time
+    let shrinker = if shrink && !count_only {
+        Some((client.clone(), options.timeout))
+    } else {
+        None
+    };
+    let cycle_progress = progress.clone();
+    let outcomes = iter_ok::<_, Error>(0..)
+        .take(if looping { u64::MAX } else { 1 })
+        .take_while(move |_| ok(deadline.is_none_or(|deadline| Instant::now() < deadline)))
+        .map(move |cycle| {
+            let seed = cycle_seed(seed, cycle);
+            let mut options = options.clone();
+            if looping {
+                cycle_progress.suspend(|| println!("Starting cycle {} with seed {}", cycle + 1, seed));
+                // the server only needs warming up once
+                if cycle > 0 {
+                    options.warmup = 0;
+                }
+            }
+            run_targets(client.clone(), base_urls.clone(), targets.clone(), n, seed, concurrency, options)
+        })
+        .flatten()
+        .map(Some)
+        .chain(iter_ok(vec![None]))
+        .select(interrupt)
+        .take_while(|outcome| ok(outcome.is_some()))
+        .filter_map(|outcome| outcome)
+        .filter(|outcome| !outcome.warmup);
+    let run = runtime.block_on(
+            outcomes
+                // outcomes of concurrent requests arrive one at a time from a
+                // single stream, and each record is written whole while holding
+                // the lock on the failure log, so records never interleave
+                .and_then(move |outcome| {
+                    let TargetOutcome {
+                        target,
+                        endpoint,
+                        base_url,
+                        seed,
+                        iteration,
+                        request,
+                        outcome,
+                        ..
+                    } = outcome;
+                    summary_recorder.lock().unwrap().record(&target, &outcome.kind, outcome.elapsed);
+                    progress_recorder.inc(1);
+                    let failures = match &failure_recorder {
+                        Some(failures) => failures.clone(),
+                        None => return Either::A(ok(())),
+                    };
+                    let ServerOutcome {
+                        method,
+                        uri,
+                        elapsed,
+                        kind,
+                    } = outcome;
+                    let kind_name = kind.name();
+                    let signature = FailureSignature::of(&kind);
+                    let category = kind.category().map(ErrorCategory::name);
+                    let version = kind.version().map(|v| format!("{:?}", v));
+                    // the path of the next body file, relative to the output directory
+                    let mut next_body_file = |dir: &str, status: StatusCode, headers: &HeaderMap| {
+                        body_count += 1;
+                        let extension = body_extension(headers);
+                        match &manifest_recorder {
+                            Some(manifest) => {
+                                let file = flat_body_path(body_count, extension);
+                                let mut manifest = manifest.lock().unwrap();
+                                if let Err(e) = manifest.record(body_count, &method, &uri, status, &file) {
+                                    error!("Could not write the body manifest: {}", e);
+                                }
+                                file
+                            }
+                            None => {
+                                let path = body_file_path(&method, &uri, body_count);
+                                format!("{}{}.{}", dir, path, extension)
+                            }
+                        }
+                    };
+                    let kind_reason = match &kind {
+                        OutcomeKind::BadBody { status, failure, .. } => Some(format!("{}: {}", status, failure)),
+                        OutcomeKind::Unexpected { status, .. } => Some(format!("Unexpected {}", status)),
+                        OutcomeKind::Forbidden { status, .. } => Some(format!("Forbidden {}", status)),
+                        _ => None,
+                    };
+                    let (status, reason, size, file) = match kind {
+                        // only the `jsonl` log records good outcomes as well
+                        OutcomeKind::Good { status, headers, body, .. } if format == OutputFormat::JsonLines => {
+                            let (size, file) = if save_all && method != Method::HEAD {
+                                let relative_path = next_body_file("good/", status, &headers);
+                                let path = outdir.join(&relative_path);
+                                let size = save_response(path, headers, body, max_body_bytes);
+                                (Either::A(size), Some(relative_path))
+                            } else {
+                                (Either::B(ok(body.content_length())), None)
+                            };
+                            (Some(status), status.to_string(), size, file)
+                        }
+                        OutcomeKind::Good { status, headers, body, .. } => {
+                            if save_all && method != Method::HEAD {
+                                let relative_path = next_body_file("good/", status, &headers);
+                                let path = outdir.join(relative_path);
+                                executor.spawn(save_response(path, headers, body, max_body_bytes).map(|_| ()));
+                            }
+                            return Either::A(ok(()));
+                        }
+                        OutcomeKind::BadError {
+                            status,
+                            headers,
+                            body,
+                            ..
+                        }
+                        | OutcomeKind::BadBody {
+                            status,
+                            headers,
+                            body,
+                            ..
+                        }
+                        | OutcomeKind::Unexpected {
+                            status,
+                            headers,
+                            body,
+                            ..
+                        }
+                        | OutcomeKind::Forbidden {
+                            status,
+                            headers,
+                            body,
+                            ..
+                        } => {
+                            // HEAD responses have no body to save
+                            let (size, file) = if method == Method::HEAD {
+                                (Either::B(ok(body.content_length())), None)
+                            } else {
+                                // write body to independent file
+                                let relative_path = next_body_file("", status, &headers);
+                                let path = outdir.join(&relative_path);
+                                let size = save_response(path, headers, body, max_body_bytes);
+                                (Either::A(size), Some(relative_path))
+                            };
+                            let reason = kind_reason.unwrap_or_else(|| status.to_string());
+                            (Some(status), reason, size, file)
+                        }
+                        OutcomeKind::BadHttp { err, .. } => {
+                            (None, err.to_string(), Either::B(ok(None)), None)
+                        }
+                        OutcomeKind::Timeout { elapsed } => {
+                            let reason = format!("Timeout after {} ms", elapsed.as_millis());
+                            (None, reason, Either::B(ok(None)), None)
+                        }
+                    };
+
+                    let curl = if emit_curl { Some(request.to_curl(http2)) } else { None };
+                    let shrunk = match (&shrinker, signature) {
+                        (Some((client, timeout)), Some(signature)) => {
+                            let shrunk = shrink_request(client.clone(), request, &endpoint, signature, *timeout);
+                            Either::A(shrunk.then(|shrunk| match shrunk {
+                                Ok(shrunk) => Ok(Some(shrunk)),
+                                Err(e) => {
+                                    error!("Could not shrink the request: {}", e);
+                                    Ok(None)
+                                }
+                            }))
+                        }
+                        _ => Either::B(ok(None)),
+                    };
+                    // the record is written once the body is saved, so that its size is known
+                    let template = uri_template(&uri, &endpoint);
+                    let size = size.then(|size| Ok(size.unwrap_or(None)));
+                    Either::B(size.join(shrunk).then(move |r: Result<_, ()>| {
+                        // neither the saving of the body nor the shrinking fails here
+                        let (size, shrunk) = r.unwrap();
+                        let (shrunk_uri, shrunk_body) = match shrunk {
+                            Some(RequestSpec { uri, body, .. }) => (Some(uri.to_string()), body),
+                            None => (None, None),
+                        };
+                        result(failures.lock().unwrap().record(template, FailureRecord {
+                            method: method.to_string(),
+                            uri: uri.to_string(),
+                            kind: kind_name,
+                            status: status.map(|s| s.as_u16()),
+                            version,
+                            reason,
+                            category,
+                            size,
+                            file,
+                            elapsed_ms: elapsed.as_millis() as u64,
+                            base_url,
+                            seed,
+                            iteration,
+                            count: 1,
+                            target,
+                            curl,
+                            shrunk_uri,
+                            shrunk_body,
+                        }))
+                    }))
                 })
                 .for_each(|_| ok(()))
                 .map_err(|e| {
@@ -283,8 +1002,34 @@ fn main() {
                     error!("\t{}", e);
                     error!("Server test stopped abruptly.");
                 })
-        )
-        .unwrap();
-    println!("Failure log recorded in {}", output_filename.display());
-    runtime.shutdown_now().wait().unwrap();
+        );
+    progress.finish_and_clear();
+    if let Some(failures) = &failures {
+        if let Err(e) = failures.lock().unwrap().finish() {
+            error!("Could not write the failure log: {}", e);
+        }
+    }
+    if let Some(manifest) = &manifest {
+        if let Err(e) = manifest.lock().unwrap().finish() {
+            error!("Could not write the body manifest: {}", e);
+        }
+    }
+    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        println!("Stopped after the maximum duration of {} seconds", max_duration.unwrap());
+    }
+    let summary = summary.lock().unwrap().clone();
+    print!("{}", summary);
+    if failures.is_some() {
+        println!("Failure log recorded in {}", output_filename.display());
+    }
+    runtime.shutdown_on_idle().wait().unwrap();
+
+    if run.is_err() {
+        std::process::exit(2);
+    }
+    let bad = summary.total.bad();
+    if bad > fail_threshold {
+        eprintln!("{} failures found (tolerating {})", bad, fail_threshold);
+        std::process::exit(1);
+    }
 }
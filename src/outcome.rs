@@ -1,42 +1,296 @@
 use http::uri::Uri;
-use http::StatusCode;
+use http::{HeaderMap, StatusCode, Version};
 use hyper::error::Error as HyperError;
 use hyper::{Body, Method};
+use std::error::Error;
+use std::io::{Error as IoError, ErrorKind};
+use std::time::Duration;
+
+use crate::request::RequestSpec;
 
 /// The outcome of a single HTTP request to the server. It either represents a
 /// "good" outcome (a reasonable response is obtained from the server), or
-/// a "bad" outcome (the server responded with a server error or the connection
-/// was cut off).
+/// a "bad" outcome (the server responded with a server error, the connection
+/// was cut off, or the server took too long to respond).
 #[derive(Debug)]
 pub struct ServerOutcome {
     /// the HTTP method of the request performed
     pub method: Method,
     /// the URI of the HTTP request performed
     pub uri: Uri,
+    /// the time taken from sending the request to receiving the response
+    pub elapsed: Duration,
     /// the kind of outcome
     pub kind: OutcomeKind,
 }
 
+/// The server outcome of a request to a test target, along with the
+/// information needed to reproduce the request
+#[derive(Debug)]
+pub struct TargetOutcome {
+    /// the name of the test target (method and endpoint)
+    pub target: String,
+    /// the endpoint of the test target
+    pub endpoint: String,
+    /// the base URL used in the request
+    pub base_url: String,
+    /// the random seed of the test target's request generator
+    pub seed: u64,
+    /// the iteration number of the request in the test target
+    pub iteration: u32,
+    /// whether the request was sent to warm up the server,
+    /// in which case the outcome should not be counted
+    pub warmup: bool,
+    /// the request sent (before following any redirects)
+    pub request: RequestSpec,
+    /// the outcome of the request
+    pub outcome: ServerOutcome,
+}
+
 impl ServerOutcome {
-    pub fn with_status(method: Method, uri: Uri, status: StatusCode, body: Body) -> Self {
+    pub fn with_status(
+        method: Method,
+        uri: Uri,
+        elapsed: Duration,
+        status: StatusCode,
+        version: Version,
+        headers: HeaderMap,
+        body: Body,
+    ) -> Self {
         ServerOutcome {
             method,
             uri,
+            elapsed,
             kind: if status.is_server_error() {
-                OutcomeKind::BadError { status, body }
+                OutcomeKind::BadError {
+                    status,
+                    version,
+                    headers,
+                    body,
+                }
             } else {
-                OutcomeKind::Good { status, body }
+                OutcomeKind::Good {
+                    status,
+                    version,
+                    headers,
+                    body,
+                }
+            },
+        }
+    }
+
+    /// Count a server error response (`5xx`) as a good outcome instead.
+    pub fn ignoring_server_error(self) -> Self {
+        let kind = match self.kind {
+            OutcomeKind::BadError {
+                status,
+                version,
+                headers,
+                body,
+            } => OutcomeKind::Good {
+                status,
+                version,
+                headers,
+                body,
+            },
+            kind => kind,
+        };
+        ServerOutcome { kind, ..self }
+    }
+
+    pub fn bad_http(method: Method, uri: Uri, elapsed: Duration, err: HyperError) -> Self {
+        ServerOutcome {
+            method,
+            uri,
+            elapsed,
+            kind: OutcomeKind::BadHttp {
+                category: ErrorCategory::of(&err),
+                err,
             },
         }
     }
 
-    pub fn bad_http(method: Method, uri: Uri, err: HyperError) -> Self {
+    pub fn unexpected(
+        method: Method,
+        uri: Uri,
+        elapsed: Duration,
+        status: StatusCode,
+        version: Version,
+        headers: HeaderMap,
+        body: Body,
+    ) -> Self {
         ServerOutcome {
             method,
             uri,
-            kind: OutcomeKind::BadHttp { err },
+            elapsed,
+            kind: OutcomeKind::Unexpected {
+                status,
+                version,
+                headers,
+                body,
+            },
+        }
+    }
+
+    pub fn forbidden(
+        method: Method,
+        uri: Uri,
+        elapsed: Duration,
+        status: StatusCode,
+        version: Version,
+        headers: HeaderMap,
+        body: Body,
+    ) -> Self {
+        ServerOutcome {
+            method,
+            uri,
+            elapsed,
+            kind: OutcomeKind::Forbidden {
+                status,
+                version,
+                headers,
+                body,
+            },
+        }
+    }
+
+    pub fn timeout(method: Method, uri: Uri, elapsed: Duration) -> Self {
+        ServerOutcome {
+            method,
+            uri,
+            elapsed,
+            kind: OutcomeKind::Timeout { elapsed },
+        }
+    }
+}
+
+impl OutcomeKind {
+    /// A short machine readable name of the outcome kind
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutcomeKind::Good { .. } => "good",
+            OutcomeKind::BadError { .. } => "server_error",
+            OutcomeKind::BadBody { .. } => "bad_body",
+            OutcomeKind::Unexpected { .. } => "unexpected_status",
+            OutcomeKind::Forbidden { .. } => "forbidden_status",
+            OutcomeKind::BadHttp { .. } => "bad_http",
+            OutcomeKind::Timeout { .. } => "timeout",
+        }
+    }
+
+    /// The category of the error behind the outcome, if any
+    pub fn category(&self) -> Option<ErrorCategory> {
+        match self {
+            OutcomeKind::BadHttp { category, .. } => Some(*category),
+            OutcomeKind::Timeout { .. } => Some(ErrorCategory::Timeout),
+            _ => None,
+        }
+    }
+
+    /// The status code of the response behind the outcome, if any
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            OutcomeKind::Good { status, .. }
+            | OutcomeKind::BadError { status, .. }
+            | OutcomeKind::BadBody { status, .. }
+            | OutcomeKind::Unexpected { status, .. }
+            | OutcomeKind::Forbidden { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// The HTTP version of the response behind the outcome, if any
+    pub fn version(&self) -> Option<Version> {
+        match self {
+            OutcomeKind::Good { version, .. }
+            | OutcomeKind::BadError { version, .. }
+            | OutcomeKind::BadBody { version, .. }
+            | OutcomeKind::Unexpected { version, .. }
+            | OutcomeKind::Forbidden { version, .. } => Some(*version),
+            _ => None,
+        }
+    }
+}
+
+/// The category of an error at the HTTP layer,
+/// telling what went wrong with the connection
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ErrorCategory {
+    /// the server's host name could not be resolved
+    Dns,
+    /// could not connect to the server
+    Connect,
+    /// the connection was reset by the server
+    Reset,
+    /// the connection was closed before the response was complete
+    IncompleteMessage,
+    /// the response could not be parsed
+    Parse,
+    /// the request was canceled
+    Canceled,
+    /// the connection was closed
+    Closed,
+    /// the server did not respond in time
+    Timeout,
+    /// any other error
+    Other,
+}
+
+impl ErrorCategory {
+    /// Classify an error from hyper.
+    pub fn of(err: &HyperError) -> Self {
+        let io_kind = io_error_kind(err);
+        if err.is_connect() {
+            let dns = sources(err).any(|e| e.to_string().contains("lookup"));
+            if dns {
+                ErrorCategory::Dns
+            } else {
+                ErrorCategory::Connect
+            }
+        } else if let Some(ErrorKind::ConnectionReset)
+        | Some(ErrorKind::ConnectionAborted)
+        | Some(ErrorKind::BrokenPipe) = io_kind
+        {
+            ErrorCategory::Reset
+        } else if err.is_incomplete_message() {
+            ErrorCategory::IncompleteMessage
+        } else if err.is_parse() {
+            ErrorCategory::Parse
+        } else if err.is_canceled() {
+            ErrorCategory::Canceled
+        } else if err.is_closed() {
+            ErrorCategory::Closed
+        } else {
+            ErrorCategory::Other
         }
     }
+
+    /// A short machine readable name of the category
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorCategory::Dns => "dns",
+            ErrorCategory::Connect => "connect",
+            ErrorCategory::Reset => "reset",
+            ErrorCategory::IncompleteMessage => "incomplete_message",
+            ErrorCategory::Parse => "parse",
+            ErrorCategory::Canceled => "canceled",
+            ErrorCategory::Closed => "closed",
+            ErrorCategory::Timeout => "timeout",
+            ErrorCategory::Other => "other",
+        }
+    }
+}
+
+/// The chain of errors behind an error
+fn sources<'a>(err: &'a HyperError) -> impl Iterator<Item = &'a (dyn Error + 'static)> {
+    std::iter::successors(err.source(), |&e| e.source())
+}
+
+/// The kind of the I/O error behind an error, if any
+fn io_error_kind(err: &HyperError) -> Option<ErrorKind> {
+    sources(err)
+        .find_map(|e| e.downcast_ref::<IoError>())
+        .map(IoError::kind)
 }
 
 #[derive(Debug)]
@@ -47,6 +301,10 @@ pub enum OutcomeKind {
     Good {
         /// the status code returned by the server
         status: StatusCode,
+        /// the HTTP version of the respective HTTP response
+        version: Version,
+        /// the headers of the respective HTTP response
+        headers: HeaderMap,
         /// the body of the respective HTTP response
         body: Body,
     },
@@ -54,9 +312,57 @@ pub enum OutcomeKind {
     BadError {
         /// the status code returned by the server (sure to be 5xx)
         status: StatusCode,
+        /// the HTTP version of the respective HTTP response
+        version: Version,
+        /// the headers of the respective HTTP response
+        headers: HeaderMap,
+        /// the body of the respective HTTP response
+        body: Body,
+    },
+    /// The body of the response failed a check of the target (bad!)
+    BadBody {
+        /// the status code returned by the server
+        status: StatusCode,
+        /// the HTTP version of the respective HTTP response
+        version: Version,
+        /// the headers of the respective HTTP response
+        headers: HeaderMap,
+        /// the body of the respective HTTP response
+        body: Body,
+        /// a description of the failed check
+        failure: String,
+    },
+    /// The server returned a status code which was not expected (bad!)
+    Unexpected {
+        /// the status code returned by the server
+        status: StatusCode,
+        /// the HTTP version of the respective HTTP response
+        version: Version,
+        /// the headers of the respective HTTP response
+        headers: HeaderMap,
+        /// the body of the respective HTTP response
+        body: Body,
+    },
+    /// The server returned a status code which the target forbids (bad!)
+    Forbidden {
+        /// the status code returned by the server
+        status: StatusCode,
+        /// the HTTP version of the respective HTTP response
+        version: Version,
+        /// the headers of the respective HTTP response
+        headers: HeaderMap,
         /// the body of the respective HTTP response
         body: Body,
     },
     /// An error emerged at the HTTP layer (bad!)
-    BadHttp { err: HyperError },
+    BadHttp {
+        err: HyperError,
+        /// the category of the error
+        category: ErrorCategory,
+    },
+    /// The server did not respond in time (bad!)
+    Timeout {
+        /// the time waited for the response
+        elapsed: Duration,
+    },
 }
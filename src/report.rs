@@ -0,0 +1,631 @@
+use serde::Serialize;
+use http::{Method, StatusCode, Uri};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::outcome::OutcomeKind;
+use crate::Error;
+
+/// The file format of the failure log
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OutputFormat {
+    /// comma separated values (`failures.csv`)
+    Csv,
+    /// one JSON object per line (`failures.jsonl`), for every outcome
+    JsonLines,
+}
+
+impl OutputFormat {
+    /// The file name of the failure log in this format
+    pub fn file_name(self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "failures.csv",
+            OutputFormat::JsonLines => "failures.jsonl",
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "jsonl" => Ok(OutputFormat::JsonLines),
+            _ => Err("Invalid output format: must be csv or jsonl"),
+        }
+    }
+}
+
+/// A single entry of the failure log
+#[derive(Debug, Clone, Serialize)]
+pub struct FailureRecord {
+    /// the HTTP method of the request performed
+    pub method: String,
+    /// the URI of the HTTP request performed
+    pub uri: String,
+    /// the kind of outcome
+    pub kind: &'static str,
+    /// the status code returned by the server, if any
+    pub status: Option<u16>,
+    /// the HTTP version of the response, if any
+    pub version: Option<String>,
+    /// a human readable description of the failure
+    pub reason: String,
+    /// the category of the error at the HTTP layer, if any
+    pub category: Option<&'static str>,
+    /// the size of the response body in bytes, if known
+    pub size: Option<u64>,
+    /// the path to the saved response body, relative to the output directory
+    pub file: Option<String>,
+    /// the time taken to obtain the outcome, in milliseconds
+    pub elapsed_ms: u64,
+    /// the base URL used in the request
+    pub base_url: String,
+    /// the random seed of the test target's request generator
+    pub seed: u64,
+    /// the iteration number of the request in the test target
+    pub iteration: u32,
+    /// the number of failures collapsed into this record
+    pub count: u32,
+    /// the name of the test target (method and endpoint)
+    pub target: String,
+    /// the request as a `curl` command, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub curl: Option<String>,
+    /// the URI of the smallest request found to fail the same way, if shrunk
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shrunk_uri: Option<String>,
+    /// the body of the smallest request found to fail the same way, if shrunk
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shrunk_body: Option<String>,
+}
+
+/// Writer of failure records to the failure log
+pub enum FailureWriter {
+    /// a CSV writer, and the columns which it writes
+    Csv(Box<csv::Writer<File>>, Vec<CsvColumn>),
+    JsonLines(BufWriter<File>),
+}
+
+/// The columns of the CSV failure log, unless others are chosen
+pub const DEFAULT_CSV_COLUMNS: [CsvColumn; 10] = [
+    CsvColumn::Method,
+    CsvColumn::Uri,
+    CsvColumn::Reason,
+    CsvColumn::Category,
+    CsvColumn::File,
+    CsvColumn::ElapsedMs,
+    CsvColumn::BaseUrl,
+    CsvColumn::Seed,
+    CsvColumn::Iteration,
+    CsvColumn::Count,
+];
+
+/// A column of the CSV failure log, after a field of the failure records
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CsvColumn {
+    Method,
+    Uri,
+    Kind,
+    Status,
+    Version,
+    Reason,
+    Category,
+    Size,
+    File,
+    ElapsedMs,
+    BaseUrl,
+    Seed,
+    Iteration,
+    Count,
+    Target,
+    Curl,
+    ShrunkUri,
+    ShrunkBody,
+}
+
+impl CsvColumn {
+    pub fn name(self) -> &'static str {
+        match self {
+            CsvColumn::Method => "method",
+            CsvColumn::Uri => "uri",
+            CsvColumn::Kind => "kind",
+            CsvColumn::Status => "status",
+            CsvColumn::Version => "version",
+            CsvColumn::Reason => "reason",
+            CsvColumn::Category => "category",
+            CsvColumn::Size => "size",
+            CsvColumn::File => "file",
+            CsvColumn::ElapsedMs => "elapsed_ms",
+            CsvColumn::BaseUrl => "base_url",
+            CsvColumn::Seed => "seed",
+            CsvColumn::Iteration => "iteration",
+            CsvColumn::Count => "count",
+            CsvColumn::Target => "target",
+            CsvColumn::Curl => "curl",
+            CsvColumn::ShrunkUri => "shrunk_uri",
+            CsvColumn::ShrunkBody => "shrunk_body",
+        }
+    }
+
+    /// The value of the column in a record. Missing values are written as
+    /// `<N/A>` in the default columns, and left empty in the others.
+    fn value(self, record: &FailureRecord) -> Cow<'_, str> {
+        fn optional(value: &Option<String>) -> Cow<'_, str> {
+            Cow::from(value.as_deref().unwrap_or(""))
+        }
+        match self {
+            CsvColumn::Method => Cow::from(record.method.as_str()),
+            CsvColumn::Uri => Cow::from(record.uri.as_str()),
+            CsvColumn::Kind => Cow::from(record.kind),
+            CsvColumn::Status => record.status.map_or(Cow::from(""), |s| s.to_string().into()),
+            CsvColumn::Version => optional(&record.version),
+            CsvColumn::Reason => Cow::from(record.reason.as_str()),
+            CsvColumn::Category => Cow::from(record.category.unwrap_or("<N/A>")),
+            CsvColumn::Size => record.size.map_or(Cow::from(""), |s| s.to_string().into()),
+            CsvColumn::File => Cow::from(record.file.as_deref().unwrap_or("<N/A>")),
+            CsvColumn::ElapsedMs => record.elapsed_ms.to_string().into(),
+            CsvColumn::BaseUrl => Cow::from(record.base_url.as_str()),
+            CsvColumn::Seed => record.seed.to_string().into(),
+            CsvColumn::Iteration => record.iteration.to_string().into(),
+            CsvColumn::Count => record.count.to_string().into(),
+            CsvColumn::Target => Cow::from(record.target.as_str()),
+            CsvColumn::Curl => optional(&record.curl),
+            CsvColumn::ShrunkUri => optional(&record.shrunk_uri),
+            CsvColumn::ShrunkBody => optional(&record.shrunk_body),
+        }
+    }
+}
+
+impl FromStr for CsvColumn {
+    type Err = String;
+
+    /// Columns are named after the fields of the failure records,
+    /// and `elapsed` also names `elapsed_ms`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim();
+        if name == "elapsed" {
+            return Ok(CsvColumn::ElapsedMs);
+        }
+        ALL_CSV_COLUMNS
+            .iter()
+            .copied()
+            .find(|c| c.name() == name)
+            .ok_or_else(|| format!("Unknown failure log column `{}`", name))
+    }
+}
+
+/// All the columns which the CSV failure log can have
+const ALL_CSV_COLUMNS: [CsvColumn; 18] = [
+    CsvColumn::Method,
+    CsvColumn::Uri,
+    CsvColumn::Kind,
+    CsvColumn::Status,
+    CsvColumn::Version,
+    CsvColumn::Reason,
+    CsvColumn::Category,
+    CsvColumn::Size,
+    CsvColumn::File,
+    CsvColumn::ElapsedMs,
+    CsvColumn::BaseUrl,
+    CsvColumn::Seed,
+    CsvColumn::Iteration,
+    CsvColumn::Count,
+    CsvColumn::Target,
+    CsvColumn::Curl,
+    CsvColumn::ShrunkUri,
+    CsvColumn::ShrunkBody,
+];
+
+impl FailureWriter {
+    /// Create a new failure log at the given path.
+    /// The CSV log has the given columns, in that order
+    /// (whereas the JSON lines log has all fields with a value).
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        format: OutputFormat,
+        columns: Vec<CsvColumn>,
+    ) -> Result<Self, Error> {
+        let file = File::create(path)?;
+        match format {
+            OutputFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(file);
+                writer.write_record(columns.iter().map(|c| c.name()))?;
+                Ok(FailureWriter::Csv(Box::new(writer), columns))
+            }
+            OutputFormat::JsonLines => Ok(FailureWriter::JsonLines(BufWriter::new(file))),
+        }
+    }
+
+    /// Write a failure record.
+    pub fn write(&mut self, record: &FailureRecord) -> Result<(), Error> {
+        match self {
+            FailureWriter::Csv(writer, columns) => {
+                let fields: Vec<_> = columns.iter().map(|c| c.value(record)).collect();
+                writer.write_record(fields.iter().map(|f| f.as_ref()))?;
+            }
+            FailureWriter::JsonLines(writer) => {
+                serde_json::to_writer(&mut *writer, record)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush the records written so far to the file.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        match self {
+            FailureWriter::Csv(writer, _) => writer.flush()?,
+            FailureWriter::JsonLines(writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// The failure log, which can collapse failures with the same signature
+/// (method, URI template and status) into a single record.
+/// Records are written one at a time, so a log shared between tasks
+/// must be behind a lock (such as `Arc<Mutex<FailureLog>>`).
+pub struct FailureLog {
+    writer: FailureWriter,
+    /// the collapsed records, in order of first appearance,
+    /// if failures are deduplicated
+    records: Option<Vec<FailureRecord>>,
+    /// the position of the record of each failure signature
+    signatures: HashMap<(String, String, Option<u16>), usize>,
+}
+
+impl FailureLog {
+    /// Create a new failure log on top of the given writer.
+    pub fn new(writer: FailureWriter, dedup: bool) -> Self {
+        FailureLog {
+            writer,
+            records: if dedup { Some(Vec::new()) } else { None },
+            signatures: HashMap::new(),
+        }
+    }
+
+    /// Record a failure, given the template of its URI (see `uri_template`).
+    /// Failures are only written right away if they are not deduplicated.
+    pub fn record(&mut self, template: String, record: FailureRecord) -> Result<(), Error> {
+        let records = match &mut self.records {
+            Some(records) => records,
+            None => return self.writer.write(&record),
+        };
+        let signature = (record.method.clone(), template, record.status);
+        match self.signatures.get(&signature) {
+            Some(&i) => records[i].count += record.count,
+            None => {
+                self.signatures.insert(signature, records.len());
+                records.push(record);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the collapsed records, if failures are deduplicated,
+    /// and flush the failure log.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        for record in self.records.take().into_iter().flatten() {
+            self.writer.write(&record)?;
+        }
+        self.writer.flush()
+    }
+}
+
+/// The manifest of the response bodies saved under sequential numbers
+/// (with `--flat-bodies`), mapping each body file to its request
+pub struct BodyManifest {
+    writer: csv::Writer<File>,
+}
+
+impl BodyManifest {
+    /// Create a new manifest at the given path.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(["id", "method", "uri", "status", "file"])?;
+        Ok(BodyManifest { writer })
+    }
+
+    /// Record the body file of the response to the given request.
+    pub fn record(
+        &mut self,
+        id: u64,
+        method: &Method,
+        uri: &Uri,
+        status: StatusCode,
+        file: &str,
+    ) -> Result<(), Error> {
+        self.writer.write_record([
+            &id.to_string(),
+            method.as_str(),
+            &uri.to_string(),
+            status.as_str(),
+            file,
+        ])?;
+        Ok(())
+    }
+
+    /// Flush the entries written so far to the file.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Normalize the URI of a request to a test target with the given endpoint,
+/// replacing the generated path segments and query string values with `{}`.
+pub fn uri_template(uri: &Uri, endpoint: &str) -> String {
+    let endpoint = format!("/{}", endpoint.trim_matches('/'));
+    let path = uri.path();
+    // the endpoint must end at a segment boundary, or `/user` would match `/users`
+    let rest = path
+        .strip_prefix(&endpoint)
+        .filter(|rest| endpoint.ends_with('/') || rest.is_empty() || rest.starts_with('/'));
+    let (mut template, rest) = match rest {
+        Some(rest) => (endpoint.trim_end_matches('/').to_string(), rest),
+        None => (String::new(), path),
+    };
+    for _ in rest.split('/').filter(|segment| !segment.is_empty()) {
+        template.push_str("/{}");
+    }
+    if let Some(query) = uri.query() {
+        let params: Vec<_> = query
+            .split('&')
+            .map(|param| match param.find('=') {
+                Some(i) => format!("{}={{}}", &param[..i]),
+                None => param.to_string(),
+            })
+            .collect();
+        template.push('?');
+        template.push_str(&params.join("&"));
+    }
+    template
+}
+
+/// Outcome counters of a set of requests
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OutcomeCounts {
+    pub good: u32,
+    pub server_error: u32,
+    pub bad_body: u32,
+    pub unexpected: u32,
+    pub forbidden: u32,
+    pub bad_http: u32,
+    pub timeout: u32,
+}
+
+impl OutcomeCounts {
+    /// Count one more outcome of the given kind.
+    pub fn record(&mut self, kind: &OutcomeKind) {
+        match kind {
+            OutcomeKind::Good { .. } => self.good += 1,
+            OutcomeKind::BadError { .. } => self.server_error += 1,
+            OutcomeKind::BadBody { .. } => self.bad_body += 1,
+            OutcomeKind::Unexpected { .. } => self.unexpected += 1,
+            OutcomeKind::Forbidden { .. } => self.forbidden += 1,
+            OutcomeKind::BadHttp { .. } => self.bad_http += 1,
+            OutcomeKind::Timeout { .. } => self.timeout += 1,
+        }
+    }
+
+    /// The total number of requests counted
+    pub fn total(&self) -> u32 {
+        self.good + self.bad()
+    }
+
+    /// The number of bad outcomes counted
+    pub fn bad(&self) -> u32 {
+        self.server_error
+            + self.bad_body
+            + self.unexpected
+            + self.forbidden
+            + self.bad_http
+            + self.timeout
+    }
+}
+
+impl fmt::Display for OutcomeCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} requests, {} good", self.total(), self.good)?;
+        let bad = [
+            (self.server_error, "server errors"),
+            (self.bad_body, "body check failures"),
+            (self.unexpected, "unexpected statuses"),
+            (self.forbidden, "forbidden statuses"),
+            (self.bad_http, "connection failures"),
+            (self.timeout, "timeouts"),
+        ];
+        for (count, label) in bad.iter().filter(|(count, _)| *count > 0) {
+            write!(f, ", {} {}", count, label)?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of buckets of response times below which each microsecond has its
+/// own bucket, and the number of buckets for each power of two above it
+const LATENCY_SUB_BUCKETS: u64 = 64;
+
+/// Number of buckets of response times, enough for any number of microseconds
+const LATENCY_BUCKETS: usize = 1920;
+
+/// The bucket of a response time in microseconds. Each bucket above the first
+/// ones spans no more than 1/32 of the times in it, so that a percentile is known
+/// within about 3%.
+fn latency_bucket(micros: u64) -> usize {
+    if micros < LATENCY_SUB_BUCKETS {
+        return micros as usize;
+    }
+    let shift = 64 - micros.leading_zeros() as u64 - 6;
+    let half = LATENCY_SUB_BUCKETS / 2;
+    (LATENCY_SUB_BUCKETS + (shift - 1) * half + ((micros >> shift) - half)) as usize
+}
+
+/// The greatest response time in microseconds of the given bucket
+fn latency_bucket_max(bucket: usize) -> u64 {
+    let bucket = bucket as u64;
+    if bucket < LATENCY_SUB_BUCKETS {
+        return bucket;
+    }
+    let half = LATENCY_SUB_BUCKETS / 2;
+    let shift = (bucket - LATENCY_SUB_BUCKETS) / half + 1;
+    let mantissa = (bucket - LATENCY_SUB_BUCKETS) % half + half;
+    ((mantissa + 1) << shift).wrapping_sub(1)
+}
+
+/// The response times of a set of requests, counted in a fixed number of
+/// buckets so that it takes the same space however many there are
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Latencies {
+    /// the number of requests by bucket of response time,
+    /// empty until the first one is recorded
+    buckets: Vec<u64>,
+    count: u32,
+    total: Duration,
+    min: Option<Duration>,
+    max: Option<Duration>,
+}
+
+impl Latencies {
+    /// Keep the time taken by one more request.
+    pub fn record(&mut self, elapsed: Duration) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; LATENCY_BUCKETS];
+        }
+        let micros = elapsed.as_micros().min(u128::from(u64::MAX)) as u64;
+        self.buckets[latency_bucket(micros)] += 1;
+        self.count += 1;
+        self.total += elapsed;
+        self.min = Some(self.min.map_or(elapsed, |min| min.min(elapsed)));
+        self.max = Some(self.max.map_or(elapsed, |max| max.max(elapsed)));
+    }
+
+    /// The least time taken, if any request was recorded
+    pub fn min(&self) -> Option<Duration> {
+        self.min
+    }
+
+    /// The greatest time taken, if any request was recorded
+    pub fn max(&self) -> Option<Duration> {
+        self.max
+    }
+
+    /// The mean time taken, if any request was recorded
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(self.total / self.count)
+    }
+
+    /// The time within which the given percentage of the requests were
+    /// completed (by the nearest rank), if any request was recorded
+    pub fn percentile(&self, percent: f64) -> Option<Duration> {
+        self.percentiles(&[percent])[0]
+    }
+
+    /// The times within which each of the given percentages (in ascending
+    /// order) of the requests were completed, as in `percentile`, going
+    /// through the buckets once for all of them
+    pub fn percentiles(&self, percents: &[f64]) -> Vec<Option<Duration>> {
+        let (min, max) = match (self.min, self.max) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return vec![None; percents.len()],
+        };
+        let count = u64::from(self.count);
+        let mut buckets = self.buckets.iter().enumerate();
+        let (mut bucket, mut seen) = (0, 0);
+        percents
+            .iter()
+            .map(|percent| {
+                let rank = ((percent / 100. * count as f64).ceil() as u64).clamp(1, count);
+                while seen < rank {
+                    match buckets.next() {
+                        Some((i, n)) => {
+                            bucket = i;
+                            seen += n;
+                        }
+                        None => break,
+                    }
+                }
+                let time = Duration::from_micros(latency_bucket_max(bucket));
+                Some(time.clamp(min, max))
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Latencies {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |d: Option<Duration>| d.unwrap_or_default().as_secs_f64() * 1000.;
+        let percentiles = self.percentiles(&[50., 95., 99.]);
+        write!(
+            f,
+            "min {:.1} ms, avg {:.1} ms, p50 {:.1} ms, p95 {:.1} ms, p99 {:.1} ms, max {:.1} ms",
+            ms(self.min()),
+            ms(self.mean()),
+            ms(percentiles[0]),
+            ms(percentiles[1]),
+            ms(percentiles[2]),
+            ms(self.max()),
+        )
+    }
+}
+
+/// Summary of all outcomes of a run, overall and by target
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Summary {
+    pub total: OutcomeCounts,
+    /// counts and response times by target, in order of first appearance
+    pub targets: Vec<(String, OutcomeCounts, Latencies)>,
+    /// counts by response status code
+    pub statuses: HashMap<StatusCode, usize>,
+}
+
+impl Summary {
+    /// Count an outcome of the given target, obtained in the given time.
+    pub fn record(&mut self, target: &str, kind: &OutcomeKind, elapsed: Duration) {
+        self.total.record(kind);
+        if let Some(status) = kind.status() {
+            *self.statuses.entry(status).or_insert(0) += 1;
+        }
+        let i = match self.targets.iter().position(|(name, ..)| name == target) {
+            Some(i) => i,
+            None => {
+                self.targets.push((target.to_string(), OutcomeCounts::default(), Latencies::default()));
+                self.targets.len() - 1
+            }
+        };
+        let (_, counts, latencies) = &mut self.targets[i];
+        counts.record(kind);
+        latencies.record(elapsed);
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Summary: {}", self.total)?;
+        for (target, counts, latencies) in &self.targets {
+            writeln!(f, "  target {}: {}", target, counts)?;
+            writeln!(f, "    latency: {}", latencies)?;
+        }
+        if !self.statuses.is_empty() {
+            let mut statuses: Vec<_> = self.statuses.iter().collect();
+            statuses.sort();
+            let statuses: Vec<_> = statuses
+                .into_iter()
+                .map(|(status, count)| format!("{}: {}", status.as_u16(), count))
+                .collect();
+            writeln!(f, "Status codes: {}", statuses.join(", "))?;
+        }
+        Ok(())
+    }
+}
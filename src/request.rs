@@ -0,0 +1,414 @@
+use chrono::DateTime;
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use http::header::{
+    HeaderMap, HeaderValue, CONTENT_ENCODING, CONTENT_TYPE, COOKIE, RETRY_AFTER, SET_COOKIE,
+};
+use http::Error as HttpError;
+use hyper::{Body, Method, Request, StatusCode, Uri};
+use futures::future::{ok, Either, FutureResult, Map};
+use futures::Future;
+use hyper::client::connect::dns::{GaiAddrs, GaiFuture, GaiResolver, Name, Resolve};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::io::{self, Read, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::DynError;
+use crate::target::ContentEncoding;
+
+/// The parts of a generated HTTP request, kept so that the request
+/// can be built again (such as when following redirects)
+#[derive(Debug, Clone)]
+pub struct RequestSpec {
+    /// the HTTP method
+    pub method: Method,
+    /// the request URI
+    pub uri: Uri,
+    /// the HTTP headers, by name and value
+    pub headers: Vec<(String, String)>,
+    /// the request body, if any
+    pub body: Option<String>,
+    /// the content type of the request body
+    pub content_type: String,
+    /// the compression of the request body, if any
+    pub compress: Option<ContentEncoding>,
+}
+
+impl RequestSpec {
+    /// Build the HTTP request.
+    /// Invalid header names or values are reported here.
+    /// Non-ASCII characters of header values are sent as their UTF-8 bytes.
+    pub fn build(&self) -> Result<Request<Body>, HttpError> {
+        let mut builder = Request::builder();
+        builder.method(self.method.clone()).uri(&self.uri);
+        for (name, value) in &self.headers {
+            builder.header(name.as_str(), value.as_bytes());
+        }
+        if self.body.is_some() {
+            builder.header(CONTENT_TYPE, self.content_type.as_str());
+            if let Some(encoding) = self.compress {
+                builder.header(CONTENT_ENCODING, encoding.name());
+            }
+        }
+        let body = match self.encoded_body() {
+            Some(body) => Body::from(body),
+            None => Body::empty(),
+        };
+        builder.body(body)
+    }
+
+    /// Obtain the request body as it is sent, compressed if requested.
+    pub fn encoded_body(&self) -> Option<Vec<u8>> {
+        let body = self.body.as_ref()?;
+        let encoded = match self.compress {
+            None => return Some(body.clone().into_bytes()),
+            Some(ContentEncoding::Gzip) => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body.as_bytes()).and_then(|_| encoder.finish())
+            }
+            Some(ContentEncoding::Deflate) => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body.as_bytes()).and_then(|_| encoder.finish())
+            }
+        };
+        // writing to memory does not fail
+        Some(encoded.expect("body should be compressed in memory"))
+    }
+
+    /// Render the request as a `curl` command line, for reproducing it in a
+    /// shell. Compressed bodies are piped through `gzip` (or `zlib` in
+    /// `python3` for deflate), whereas the body framing is not reproduced.
+    pub fn to_curl(&self, http2: bool) -> String {
+        let mut args = vec!["curl".to_string()];
+        if http2 {
+            args.push("--http2-prior-knowledge".to_string());
+        }
+        match self.method {
+            Method::GET => {}
+            Method::HEAD => args.push("--head".to_string()),
+            ref method => {
+                args.push("-X".to_string());
+                args.push(method.to_string());
+            }
+        }
+        let mut headers: Vec<(&str, &str)> =
+            self.headers.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+        if self.body.is_some() {
+            headers.push((CONTENT_TYPE.as_str(), &self.content_type));
+            if let Some(encoding) = self.compress {
+                headers.push((CONTENT_ENCODING.as_str(), encoding.name()));
+            }
+        }
+        for (name, value) in headers {
+            args.push("-H".to_string());
+            args.push(shell_quote(&format!("{}: {}", name, value)));
+        }
+        // a compressed body is piped into curl through the compressor
+        let mut pipe = String::new();
+        if let Some(body) = &self.body {
+            args.push("--data-binary".to_string());
+            match self.compress {
+                None => args.push(shell_quote(body)),
+                Some(encoding) => {
+                    let compressor = match encoding {
+                        ContentEncoding::Gzip => "gzip -c",
+                        // there is no common command line tool for zlib streams
+                        ContentEncoding::Deflate => {
+                            "python3 -c 'import sys, zlib; \
+                             sys.stdout.buffer.write(zlib.compress(sys.stdin.buffer.read()))'"
+                        }
+                    };
+                    pipe = format!("printf '%s' {} | {} | ", shell_quote(body), compressor);
+                    args.push("@-".to_string());
+                }
+            }
+        }
+        args.push(shell_quote(&self.uri.to_string()));
+        pipe + &args.join(" ")
+    }
+
+    /// Obtain the request to perform after being redirected
+    /// with the given status code to the given URI.
+    /// `307` and `308` preserve the method and body,
+    /// whereas other redirections continue with a `GET` request.
+    pub fn redirect(&self, status: StatusCode, uri: Uri) -> RequestSpec {
+        match status {
+            StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT => RequestSpec {
+                uri,
+                ..self.clone()
+            },
+            _ => RequestSpec {
+                method: if self.method == Method::HEAD {
+                    Method::HEAD
+                } else {
+                    Method::GET
+                },
+                uri,
+                body: None,
+                ..self.clone()
+            },
+        }
+    }
+}
+
+/// Maximum number of bytes of a response body decompressed for inspection
+const MAX_DECODED_BODY_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Decompress a response body for inspection, following its
+/// `Content-Encoding` (`gzip` or `deflate`), up to `MAX_DECODED_BODY_BYTES`.
+/// Bodies in other encodings, or which fail to decompress, are left as they
+/// are, as bytes which need not be UTF-8.
+pub fn decoded_body<'a>(headers: &HeaderMap, body: &'a [u8]) -> Cow<'a, [u8]> {
+    let encoding = match headers.get(CONTENT_ENCODING).and_then(|v| v.to_str().ok()) {
+        Some(encoding) => encoding.trim().to_ascii_lowercase(),
+        None => return Cow::Borrowed(body),
+    };
+    let mut decoded = Vec::new();
+    let read = match encoding.as_str() {
+        "gzip" | "x-gzip" => GzDecoder::new(body).take(MAX_DECODED_BODY_BYTES).read_to_end(&mut decoded),
+        // deflate is meant to be in the zlib format, but is sometimes raw
+        "deflate" => ZlibDecoder::new(body)
+            .take(MAX_DECODED_BODY_BYTES)
+            .read_to_end(&mut decoded)
+            .or_else(|_| {
+                decoded.clear();
+                DeflateDecoder::new(body).take(MAX_DECODED_BODY_BYTES).read_to_end(&mut decoded)
+            }),
+        _ => return Cow::Borrowed(body),
+    };
+    match read {
+        Ok(_) => Cow::Owned(decoded),
+        Err(_) => Cow::Borrowed(body),
+    }
+}
+
+/// Quote a string for a POSIX shell, in single quotes.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Resolve the value of a `Location` header against the URI of the request
+/// which was redirected.
+pub fn resolve_location(base: &Uri, location: &str) -> Option<Uri> {
+    if location.contains("://") {
+        return location.parse().ok();
+    }
+    let scheme = base.scheme_part()?;
+    let authority = base.authority_part()?;
+    if location.starts_with("//") {
+        return format!("{}:{}", scheme, location).parse().ok();
+    }
+    let path = if location.starts_with('/') {
+        location.to_string()
+    } else {
+        // relative to the base path's directory
+        let base_path = base.path();
+        let dir = &base_path[..=base_path.rfind('/').unwrap_or(0)];
+        let dir = if dir.is_empty() { "/" } else { dir };
+        format!("{}{}", dir, location)
+    };
+    format!("{}://{}{}", scheme, authority, path).parse().ok()
+}
+
+/// The time to wait before sending more requests, as told by the
+/// `Retry-After` header (in seconds or as an HTTP date), if any
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    let wait = date.timestamp() - now.as_secs() as i64;
+    Some(Duration::from_secs(wait.max(0) as u64))
+}
+
+/// Credentials for the `Authorization` header
+#[derive(Debug, Clone, PartialEq)]
+pub enum Auth {
+    /// HTTP basic authentication
+    Basic { user: String, password: String },
+    /// bearer token authentication
+    Bearer { token: String },
+}
+
+impl Auth {
+    /// The value of the `Authorization` header with these credentials
+    pub fn header_value(&self) -> String {
+        match self {
+            Auth::Basic { user, password } => {
+                format!("Basic {}", base64::encode(format!("{}:{}", user, password)))
+            }
+            Auth::Bearer { token } => format!("Bearer {}", token),
+        }
+    }
+}
+
+impl FromStr for Auth {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("basic", credentials)) => {
+                let (user, password) = credentials
+                    .split_once(':')
+                    .ok_or("Invalid basic credentials: must be basic:user:pass")?;
+                Ok(Auth::Basic {
+                    user: user.to_string(),
+                    password: password.to_string(),
+                })
+            }
+            Some(("bearer", token)) => Ok(Auth::Bearer {
+                token: token.to_string(),
+            }),
+            _ => Err("Invalid credentials: must be basic:user:pass or bearer:token"),
+        }
+    }
+}
+
+/// Read HTTP headers from a file of `Name: Value` lines, to include in all
+/// requests. Blank lines and lines starting with `#` are skipped.
+pub fn read_headers_file<P: AsRef<Path>>(path: P) -> Result<Vec<(String, String)>, DynError> {
+    let text = read_to_string(path)?;
+    let mut headers = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once(':') {
+            Some((name, value)) if !name.trim().is_empty() => {
+                headers.push((name.trim().to_string(), value.trim().to_string()))
+            }
+            _ => return Err(format!("Invalid header on line {}: expected `Name: Value`", i + 1).into()),
+        }
+    }
+    Ok(headers)
+}
+
+/// A jar of cookies set by the servers, by host, to be sent back in
+/// subsequent requests. Cookie attributes other than expiration
+/// (such as the path and the domain) are not taken into account.
+#[derive(Debug, Default)]
+pub struct CookieJar {
+    cookies: HashMap<String, Vec<(String, String)>>,
+}
+
+impl CookieJar {
+    /// Keep the cookies set in the response to a request to the given URI.
+    pub fn store(&mut self, uri: &Uri, headers: &HeaderMap) {
+        let host = match uri.host() {
+            Some(host) => host,
+            None => return,
+        };
+        for set_cookie in headers.get_all(SET_COOKIE) {
+            let set_cookie = match set_cookie.to_str() {
+                Ok(set_cookie) => set_cookie,
+                Err(_) => continue,
+            };
+            let mut attributes = set_cookie.split(';').map(str::trim);
+            let (name, value) = match attributes.next().and_then(|c| c.split_once('=')) {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => continue,
+            };
+            // an expired cookie is the server's way of removing it
+            let expired = attributes.any(|a| {
+                let a = a.to_ascii_lowercase();
+                a == "max-age=0" || a.starts_with("max-age=-")
+            });
+            let cookies = self.cookies.entry(host.to_string()).or_default();
+            cookies.retain(|(n, _)| n != name);
+            if !expired {
+                cookies.push((name.to_string(), value.to_string()));
+            }
+        }
+    }
+
+    /// Add the cookies kept for the request's host to the request,
+    /// after any cookies which the request already has.
+    pub fn apply(&self, req: &mut Request<Body>) {
+        let cookies = match req.uri().host().and_then(|host| self.cookies.get(host)) {
+            Some(cookies) if !cookies.is_empty() => cookies,
+            _ => return,
+        };
+        // the cookies already given in the request's headers
+        let given = req
+            .headers()
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .map(String::from);
+        let value = given
+            .chain(cookies.iter().map(|(name, value)| format!("{}={}", name, value)))
+            .collect::<Vec<_>>()
+            .join("; ");
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            req.headers_mut().insert(COOKIE, value);
+        }
+    }
+}
+
+/// A host name to resolve to the given address, instead of asking DNS
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolveOverride {
+    pub host: String,
+    pub addr: IpAddr,
+}
+
+impl FromStr for ResolveOverride {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (host, addr) = s
+            .split_once(':')
+            .ok_or("Invalid address override: must be host:address")?;
+        // IPv6 addresses may be in brackets, as in URLs
+        let addr = addr.trim_start_matches('[').trim_end_matches(']');
+        Ok(ResolveOverride {
+            host: host.to_ascii_lowercase(),
+            addr: addr.parse().map_err(|_| "Invalid address override: bad IP address")?,
+        })
+    }
+}
+
+/// A DNS resolver which resolves some host names to fixed addresses,
+/// and all others as usual
+#[derive(Debug, Clone)]
+pub struct OverrideResolver {
+    overrides: Arc<HashMap<String, IpAddr>>,
+    fallback: GaiResolver,
+}
+
+impl OverrideResolver {
+    pub fn new(overrides: Vec<ResolveOverride>, fallback: GaiResolver) -> Self {
+        OverrideResolver {
+            overrides: Arc::new(overrides.into_iter().map(|o| (o.host, o.addr)).collect()),
+            fallback,
+        }
+    }
+}
+
+impl Resolve for OverrideResolver {
+    type Addrs = std::vec::IntoIter<IpAddr>;
+    type Future = Either<
+        FutureResult<Self::Addrs, io::Error>,
+        Map<GaiFuture, fn(GaiAddrs) -> Self::Addrs>,
+    >;
+
+    fn resolve(&self, name: Name) -> Self::Future {
+        match self.overrides.get(&name.as_str().to_ascii_lowercase()) {
+            Some(addr) => Either::A(ok(vec![*addr].into_iter())),
+            None => {
+                let collect: fn(GaiAddrs) -> Self::Addrs = |addrs| addrs.collect::<Vec<_>>().into_iter();
+                Either::B(self.fallback.resolve(name).map(collect))
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! Driving the request generators with external entropy.
+//!
+//! All generators take any [`Rng`](rand::Rng), so they can also be driven by
+//! the input of a fuzzer (such as cargo-fuzz or AFL) through [`ByteRng`],
+//! turning fuzzer bytes into structured requests.
+
+use rand::{Error as RandError, RngCore};
+
+/// A random number generator which takes its entropy from a byte slice,
+/// such as the input of a fuzzer. The same bytes always make the same
+/// values, and once the bytes run out, only zeros are produced.
+#[derive(Debug, Clone)]
+pub struct ByteRng<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ByteRng<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        ByteRng { bytes }
+    }
+
+    /// The number of bytes not consumed yet
+    pub fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+impl RngCore for ByteRng<'_> {
+    fn next_u32(&mut self) -> u32 {
+        let mut buf = [0; 4];
+        self.fill_bytes(&mut buf);
+        u32::from_le_bytes(buf)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut buf = [0; 8];
+        self.fill_bytes(&mut buf);
+        u64::from_le_bytes(buf)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        let n = dest.len().min(self.bytes.len());
+        let (taken, rest) = self.bytes.split_at(n);
+        dest[..n].copy_from_slice(taken);
+        for byte in &mut dest[n..] {
+            *byte = 0;
+        }
+        self.bytes = rest;
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), RandError> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+//! Shrinking failing requests into smaller ones which fail the same way.
+//!
+//! The generated values of a failing request (the path after the endpoint,
+//! split into segments, the query string values and the body) are simplified
+//! one at a time, by removing ever smaller chunks of each value, for as long
+//! as the server keeps failing in the same way.
+
+use futures::future::{loop_fn, ok, Either, Loop};
+use futures::Future;
+use hyper::client::connect::Connect;
+use hyper::{Client, StatusCode};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::timer::timeout::{Error as TimeoutError, Timeout};
+
+use crate::outcome::{ErrorCategory, OutcomeKind};
+use crate::request::RequestSpec;
+use crate::Error;
+
+/// Maximum number of requests sent to shrink a single failing request
+pub const MAX_SHRINK_ATTEMPTS: usize = 100;
+
+/// The way in which a request fails, which its shrunk versions must keep
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FailureSignature {
+    /// a response with this status code
+    Status(StatusCode),
+    /// an error of this category at the HTTP layer
+    Http(ErrorCategory),
+    /// no response in time
+    Timeout,
+}
+
+impl FailureSignature {
+    /// The signature of the failure behind an outcome, if it is a failure
+    /// which can be told apart without the response body
+    pub fn of(kind: &OutcomeKind) -> Option<Self> {
+        match kind {
+            OutcomeKind::BadError { status, .. }
+            | OutcomeKind::Unexpected { status, .. }
+            | OutcomeKind::Forbidden { status, .. } => Some(FailureSignature::Status(*status)),
+            OutcomeKind::BadHttp { category, .. } => Some(FailureSignature::Http(*category)),
+            OutcomeKind::Timeout { .. } => Some(FailureSignature::Timeout),
+            OutcomeKind::Good { .. } | OutcomeKind::BadBody { .. } => None,
+        }
+    }
+}
+
+/// The generated values of a request, which are shrunk one at a time
+#[derive(Debug, Clone)]
+struct Parts {
+    /// the scheme, authority and endpoint of the URI
+    prefix: String,
+    /// the rest of the path, split at each `/`
+    segments: Vec<String>,
+    /// the query string parameters, by name and value (if any)
+    query: Vec<(String, Option<String>)>,
+    body: Option<String>,
+}
+
+impl Parts {
+    fn of(spec: &RequestSpec, endpoint: &str) -> Self {
+        let uri = &spec.uri;
+        let mut prefix = match (uri.scheme_part(), uri.authority_part()) {
+            (Some(scheme), Some(authority)) => format!("{}://{}", scheme, authority),
+            _ => String::new(),
+        };
+        let endpoint = format!("/{}", endpoint.trim_matches('/'));
+        let endpoint = endpoint.trim_end_matches('/');
+        let path = uri.path();
+        let rest = match path.strip_prefix(endpoint) {
+            Some(rest) => {
+                prefix.push_str(endpoint);
+                rest
+            }
+            None => path,
+        };
+        let query = uri
+            .query()
+            .into_iter()
+            .flat_map(|query| query.split('&'))
+            .map(|param| match param.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (param.to_string(), None),
+            })
+            .collect();
+        Parts {
+            prefix,
+            segments: rest.split('/').map(String::from).collect(),
+            query,
+            body: spec.body.clone(),
+        }
+    }
+
+    /// The number of values which may be shrunk
+    fn len(&self) -> usize {
+        self.segments.len() + self.query.len() + 1
+    }
+
+    /// The value at the given position, if there is a value there
+    fn value_mut(&mut self, i: usize) -> Option<&mut String> {
+        let (segments, query) = (self.segments.len(), self.query.len());
+        if i < segments {
+            Some(&mut self.segments[i])
+        } else if i < segments + query {
+            self.query[i - segments].1.as_mut()
+        } else {
+            self.body.as_mut()
+        }
+    }
+
+    /// Build the request with these values, unless the URI is not valid.
+    fn to_spec(&self, original: &RequestSpec) -> Option<RequestSpec> {
+        let mut uri = self.prefix.clone();
+        uri.push_str(&self.segments.join("/"));
+        for (i, (name, value)) in self.query.iter().enumerate() {
+            uri.push(if i == 0 { '?' } else { '&' });
+            uri.push_str(name);
+            if let Some(value) = value {
+                uri.push('=');
+                uri.push_str(value);
+            }
+        }
+        Some(RequestSpec {
+            uri: uri.parse().ok()?,
+            body: self.body.clone(),
+            ..original.clone()
+        })
+    }
+}
+
+/// The state of the shrinking of a request
+struct Shrinker {
+    /// the original request, for its method and headers
+    original: RequestSpec,
+    /// the smallest values which still fail the same way
+    best: Parts,
+    /// the position of the value being shrunk
+    value: usize,
+    /// the number of characters to remove at once from the value,
+    /// if started on the value
+    chunk: Option<usize>,
+    /// the position of the next characters to remove from the value
+    pos: usize,
+    attempts: usize,
+}
+
+impl Shrinker {
+    fn new(original: RequestSpec, endpoint: &str) -> Self {
+        Shrinker {
+            best: Parts::of(&original, endpoint),
+            original,
+            value: 0,
+            chunk: None,
+            pos: 0,
+            attempts: 0,
+        }
+    }
+
+    /// Build the next smaller request to try, if there is any left.
+    fn next_candidate(&mut self) -> Option<(Parts, RequestSpec)> {
+        while self.attempts < MAX_SHRINK_ATTEMPTS && self.value < self.best.len() {
+            let value = match self.best.value_mut(self.value) {
+                Some(value) => value.clone(),
+                None => {
+                    self.next_value();
+                    continue;
+                }
+            };
+            let len = value.chars().count();
+            let chunk = *self.chunk.get_or_insert(len);
+            if chunk == 0 {
+                self.next_value();
+                continue;
+            }
+            if self.pos >= len {
+                // try again with smaller chunks
+                self.chunk = Some(chunk / 2);
+                self.pos = 0;
+                continue;
+            }
+            let mut candidate = self.best.clone();
+            *candidate.value_mut(self.value).unwrap() = value
+                .chars()
+                .take(self.pos)
+                .chain(value.chars().skip(self.pos + chunk))
+                .collect();
+            self.attempts += 1;
+            match candidate.to_spec(&self.original) {
+                Some(spec) => return Some((candidate, spec)),
+                None => self.pos += chunk,
+            }
+        }
+        None
+    }
+
+    fn next_value(&mut self) {
+        self.value += 1;
+        self.chunk = None;
+        self.pos = 0;
+    }
+
+    /// Keep the candidate which failed the same way. The chunk after the one
+    /// removed is now at the same position, so the position is kept.
+    fn accept(&mut self, candidate: Parts) {
+        self.best = candidate;
+    }
+
+    /// Move on from a candidate which did not fail the same way.
+    fn reject(&mut self) {
+        self.pos += self.chunk.unwrap_or(0).max(1);
+    }
+
+    /// The smallest request which failed the same way
+    fn into_best(self) -> RequestSpec {
+        self.best.to_spec(&self.original).unwrap_or(self.original)
+    }
+}
+
+/// Shrink a failing request to a test target with the given endpoint,
+/// sending smaller versions of it to the server (up to
+/// `MAX_SHRINK_ATTEMPTS`). Resolves to the smallest request which still
+/// fails with the given signature, which is the original request if no
+/// smaller one does. Redirects are not followed.
+pub fn shrink_request<C>(
+    client: Arc<Client<C>>,
+    request: RequestSpec,
+    endpoint: &str,
+    signature: FailureSignature,
+    timeout: Option<Duration>,
+) -> impl Future<Item = RequestSpec, Error = Error>
+where
+    C: Connect + 'static,
+{
+    loop_fn(Shrinker::new(request, endpoint), move |mut shrinker| {
+        match shrinker.next_candidate() {
+            None => Either::A(ok(Loop::Break(shrinker.into_best()))),
+            Some((candidate, spec)) => Either::B(
+                fails_with(&client, &spec, signature, timeout).map(move |same| {
+                    if same {
+                        shrinker.accept(candidate);
+                    } else {
+                        shrinker.reject();
+                    }
+                    Loop::Continue(shrinker)
+                }),
+            ),
+        }
+    })
+}
+
+/// Send a request, resolving to whether it fails with the given signature.
+fn fails_with<C>(
+    client: &Client<C>,
+    spec: &RequestSpec,
+    signature: FailureSignature,
+    timeout: Option<Duration>,
+) -> impl Future<Item = bool, Error = Error>
+where
+    C: Connect + 'static,
+{
+    let req = match spec.build() {
+        Ok(req) => req,
+        Err(_) => return Either::A(ok(false)),
+    };
+    let response = client.request(req);
+    let response = match timeout {
+        Some(timeout) => Either::A(Timeout::new(response, timeout)),
+        None => Either::B(response.map_err(TimeoutError::inner)),
+    };
+    Either::B(response.then(move |r| match r {
+        Ok(response) => Ok(signature == FailureSignature::Status(response.status())),
+        Err(e) if e.is_elapsed() => Ok(signature == FailureSignature::Timeout),
+        Err(e) if e.is_timer() => Err(Error::Timer {
+            err: e.into_timer().unwrap(),
+        }),
+        Err(e) => {
+            let err = e.into_inner().unwrap();
+            Ok(signature == FailureSignature::Http(ErrorCategory::of(&err)))
+        }
+    }))
+}
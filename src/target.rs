@@ -1,9 +1,29 @@
+use chrono::{Datelike, Duration as DateDuration, NaiveDate};
+use http::header::{HeaderName, HeaderValue, HOST};
 use http::uri::InvalidUri;
-use hyper::{Method as HyperMethod, Uri};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS, NON_ALPHANUMERIC};
+use hyper::{Method as HyperMethod, StatusCode, Uri};
+use rand::distributions::{Distribution, WeightedIndex};
 use rand::seq::SliceRandom;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::env::VarError;
+use std::fmt::Write;
+use std::fs::{read_to_string, File};
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use crate::capture::{Capture, CapturedValues};
+use crate::config::DynError;
+
+/// Maximum number of repetitions produced by unbounded regex quantifiers
+/// (such as `*` and `+`)
+const REGEX_MAX_REPEAT: u32 = 32;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Method {
@@ -11,17 +31,24 @@ pub enum Method {
     #[serde(rename = "put")] Put,
     #[serde(rename = "post")] Post,
     #[serde(rename = "delete")] Delete,
+    #[serde(rename = "patch")] Patch,
+    #[serde(rename = "head")] Head,
+    #[serde(rename = "options")] Options,
 }
 
 impl FromStr for Method {
     type Err = &'static str;
 
+    /// Method names are case-insensitive.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "GET" | "get" | "Get" => Ok(Method::Get),
-            "PUT" | "put" | "Put" => Ok(Method::Put),
-            "POST" | "post" | "Post" => Ok(Method::Post),
-            "DELETE" | "delete" | "Delete" => Ok(Method::Delete),
+        match s.to_ascii_uppercase().as_str() {
+            "GET" => Ok(Method::Get),
+            "PUT" => Ok(Method::Put),
+            "POST" => Ok(Method::Post),
+            "DELETE" => Ok(Method::Delete),
+            "PATCH" => Ok(Method::Patch),
+            "HEAD" => Ok(Method::Head),
+            "OPTIONS" => Ok(Method::Options),
             _ => Err("Invalid method"),
         }
     }
@@ -34,6 +61,9 @@ impl From<Method> for HyperMethod {
             Method::Put => HyperMethod::PUT,
             Method::Post => HyperMethod::POST,
             Method::Delete => HyperMethod::DELETE,
+            Method::Patch => HyperMethod::PATCH,
+            Method::Head => HyperMethod::HEAD,
+            Method::Options => HyperMethod::OPTIONS,
         }
     }
 }
@@ -42,51 +72,468 @@ impl From<Method> for HyperMethod {
 pub struct TestTarget {
     /// HTTP endpoint relative to the URI
     pub endpoint: String,
+    /// The base path of the endpoint, such as `/api/v2`
+    /// (overrides the prefix of the configuration)
+    #[serde(default)]
+    pub prefix: Option<String>,
     /// HTTP method
     pub method: Method,
     /// The methods to randomly test
     pub args: Vec<TestArg>,
+    /// The generator of the request body (only used in POST, PUT and PATCH)
+    #[serde(default)]
+    pub body: Option<ArgGenerator>,
+    /// The content type of the request body
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+    /// The response status codes considered acceptable
+    /// (if omitted, only server errors are considered bad)
+    #[serde(default)]
+    pub expect_status: Option<Vec<StatusPattern>>,
+    /// The response status codes considered bad, even if expected
+    /// (such as `2xx` from a protected endpoint)
+    #[serde(default)]
+    pub forbid_status: Vec<StatusPattern>,
+    /// The strings which the body of each response must contain
+    /// (server errors aside)
+    #[serde(default)]
+    pub body_must_contain: Vec<String>,
+    /// The strings which the body of each response must not contain,
+    /// such as an error message in a `200` response (server errors aside)
+    #[serde(default)]
+    pub body_must_not_contain: Vec<String>,
+    /// The HTTP headers to include in each request
+    #[serde(default)]
+    pub headers: Vec<HeaderSpec>,
+    /// The number of iterations to test for this target
+    /// (overrides the global number of iterations)
+    #[serde(default)]
+    pub iterations: Option<u32>,
+    /// Whether to place the sampled path and query string values in the URI
+    /// as they are, instead of percent-encoding characters not allowed there
+    /// (for intentionally malformed requests). Requests with values which
+    /// still do not make a valid URI (such as with spaces or non-ASCII
+    /// characters) are not sent, and a `#` starts the fragment of the URI,
+    /// which is not sent either.
+    #[serde(default)]
+    pub raw: bool,
+    /// The query string parameters to include in every request,
+    /// after the sampled ones, by name and value
+    #[serde(default)]
+    pub fixed_query: Vec<(String, String)>,
+    /// How to frame the request body, possibly declaring a wrong length
+    /// (for testing the server's handling of malformed bodies)
+    #[serde(default)]
+    pub body_framing: BodyFraming,
+    /// How to compress the request body, if at all
+    /// (for testing the server's decompression)
+    #[serde(default)]
+    pub compress: Option<ContentEncoding>,
+    /// The generator of the `Host` header, which is otherwise
+    /// derived from the request URI (for testing virtual host routing)
+    #[serde(default)]
+    pub host_header: Option<ArgGenerator>,
+    /// The values to capture from the bodies of successful responses,
+    /// for use by later targets
+    #[serde(default)]
+    pub capture: Vec<Capture>,
+    /// Whether any generator of the target uses captured values,
+    /// known once the configuration is prepared
+    #[serde(skip)]
+    pub uses_captures: bool,
+}
+
+/// The compression of a request body
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentEncoding {
+    /// gzip (RFC 1952)
+    Gzip,
+    /// deflate, in the zlib format (RFC 1950)
+    Deflate,
+}
+
+impl ContentEncoding {
+    /// The name of the encoding, as in the `Content-Encoding` header
+    pub fn name(self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+        }
+    }
+}
+
+/// The framing of a request body
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BodyFraming {
+    /// a correct `Content-Length`, as usual
+    #[default]
+    Exact,
+    /// a `Content-Length` greater than the length of the body
+    Overstate,
+    /// a `Content-Length` smaller than the length of the body
+    Understate,
+    /// a malformed chunked encoding
+    ChunkedMalformed,
+}
+
+/// An HTTP header to include in the request
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct HeaderSpec {
+    /// the header name
+    pub name: String,
+    /// the generator of the header value
+    #[serde(default)]
+    pub value: ArgGenerator,
+}
+
+/// Maximum number of repetitions of a query string parameter
+const MAX_QUERY_REPEAT: i64 = 100;
+
+/// Probability of including an optional query string parameter,
+/// unless the parameter gives its own
+const DEFAULT_PRESENCE_CHANCE: f64 = 0.5;
+
+/// Characters to percent-encode in a sampled path segment.
+/// `%` is kept, so that values can be given already percent-encoded.
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}')
+    .add(b'/')
+    .add(b'\\')
+    .add(b'^')
+    .add(b'|')
+    .add(b'[')
+    .add(b']');
+
+/// Characters to percent-encode in a sampled query string name or value
+const QUERY_COMPONENT: &AsciiSet = &PATH_SEGMENT.add(b'&').add(b'=').add(b'+');
+
+/// Characters to percent-encode in a sampled matrix parameter name or value
+const MATRIX_PARAM: &AsciiSet = &PATH_SEGMENT.add(b';').add(b'=');
+
+/// Characters to percent-encode in a sampled value spanning path segments
+const PATH_SEGMENTS: &AsciiSet = &PATH_SEGMENT.remove(b'/');
+
+fn default_content_type() -> String {
+    "application/octet-stream".to_string()
 }
 
 impl TestTarget {
+    /// Create a test target without a request body.
+    pub fn new(endpoint: String, method: Method, args: Vec<TestArg>) -> Self {
+        TestTarget {
+            endpoint,
+            prefix: None,
+            method,
+            args,
+            body: None,
+            content_type: default_content_type(),
+            expect_status: None,
+            forbid_status: Vec::new(),
+            body_must_contain: Vec::new(),
+            body_must_not_contain: Vec::new(),
+            headers: Vec::new(),
+            iterations: None,
+            raw: false,
+            fixed_query: Vec::new(),
+            body_framing: BodyFraming::Exact,
+            compress: None,
+            host_header: None,
+            capture: Vec::new(),
+            uses_captures: false,
+        }
+    }
+
     pub fn method(&self) -> HyperMethod {
         self.method.into()
     }
 
-    /// Randomly build an HTTP request in order to test this target.
-    pub fn sample<R>(&self, base_url: &str, rng: &mut R) -> Result<Uri, InvalidUri>
+    /// The endpoint after the prefix, if there is one
+    pub fn path(&self) -> Cow<'_, str> {
+        match self.prefix.as_deref().map(|prefix| prefix.trim_matches('/')) {
+            Some(prefix) if !prefix.is_empty() => {
+                format!("/{}/{}", prefix, self.endpoint.trim_start_matches('/')).into()
+            }
+            _ => Cow::Borrowed(&self.endpoint),
+        }
+    }
+
+    /// Check whether the given response status is unexpected for this
+    /// target. Server errors are not covered here, as they are always bad.
+    pub fn is_unexpected_status(&self, status: StatusCode) -> bool {
+        match &self.expect_status {
+            Some(patterns) => {
+                !status.is_server_error() && !patterns.iter().any(|p| p.matches(status))
+            }
+            None => false,
+        }
+    }
+
+    /// Check whether the given response status is forbidden for this
+    /// target. Server errors are not covered here, as they are always bad.
+    pub fn is_forbidden_status(&self, status: StatusCode) -> bool {
+        !status.is_server_error() && self.forbid_status.iter().any(|p| p.matches(status))
+    }
+
+    /// Whether the response bodies of this target are checked
+    /// for the strings which they must (not) contain
+    pub fn checks_body(&self) -> bool {
+        !self.body_must_contain.is_empty() || !self.body_must_not_contain.is_empty()
+    }
+
+    /// Check a response body for the strings which it must (not) contain,
+    /// describing the first failed check, if any.
+    pub fn check_body(&self, body: &[u8]) -> Option<String> {
+        let contains = |text: &str| {
+            let text = text.as_bytes();
+            text.is_empty() || body.windows(text.len()).any(|w| w == text)
+        };
+        if let Some(text) = self.body_must_contain.iter().find(|text| !contains(text)) {
+            return Some(format!("body does not contain {:?}", text));
+        }
+        self.body_must_not_contain
+            .iter()
+            .find(|text| contains(text))
+            .map(|text| format!("body contains {:?}", text))
+    }
+
+    /// Check the target and all of its generators for problems,
+    /// returning a description of each problem found.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (i, arg) in self.args.iter().enumerate() {
+            for problem in arg.validate() {
+                problems.push(format!("argument #{}: {}", i + 1, problem));
+            }
+        }
+        if let Some(body) = &self.body {
+            for problem in body.validate() {
+                problems.push(format!("body: {}", problem));
+            }
+        }
+        if HeaderValue::from_str(&self.content_type).is_err() {
+            problems.push(format!("invalid content type '{}'", self.content_type));
+        }
+        let expected = self.expect_status.iter().flatten().map(|p| ("expect_status", p));
+        let forbidden = self.forbid_status.iter().map(|p| ("forbid_status", p));
+        for (field, pattern) in expected.chain(forbidden) {
+            if let StatusPattern::Range { low, high } = pattern {
+                if low > high {
+                    problems.push(format!("{}: low {} is greater than high {}", field, low, high));
+                }
+            }
+        }
+        for header in &self.headers {
+            if HeaderName::from_bytes(header.name.as_bytes()).is_err() {
+                problems.push(format!("invalid header name '{}'", header.name));
+            }
+            for problem in header.value.validate() {
+                problems.push(format!("header {}: {}", header.name, problem));
+            }
+        }
+        if let Some(host) = &self.host_header {
+            for problem in host.validate() {
+                problems.push(format!("host_header: {}", problem));
+            }
+        }
+        for capture in &self.capture {
+            problems.extend(capture.validate());
+        }
+        problems
+    }
+
+    /// Validate and prepare all argument generators of this target
+    /// for sampling.
+    pub fn prepare(&mut self) -> Result<(), DynError> {
+        for arg in &mut self.args {
+            arg.prepare()?;
+        }
+        if let Some(body) = &mut self.body {
+            body.prepare()?;
+        }
+        for header in &mut self.headers {
+            header.value.prepare()?;
+        }
+        if let Some(host) = &mut self.host_header {
+            host.prepare()?;
+        }
+        for capture in &mut self.capture {
+            capture.prepare()?;
+        }
+        Ok(())
+    }
+
+    /// Visit the `captured` generators of this target, by name.
+    pub fn visit_captured(&mut self, f: &mut dyn FnMut(&str, &mut CapturedValues)) {
+        for arg in &mut self.args {
+            arg.visit_captured(f);
+        }
+        let headers = self.headers.iter_mut().map(|h| &mut h.value);
+        for g in self.body.iter_mut().chain(headers).chain(&mut self.host_header) {
+            g.visit_captured(f);
+        }
+    }
+
+    /// Randomly build the HTTP headers of a request for this target,
+    /// including the `Host` header if it is generated. Values which cannot
+    /// be sent in a header (such as those with line breaks) are sampled
+    /// again, up to `MAX_HEADER_ATTEMPTS` times, after which the last one is
+    /// kept and the request cannot be built.
+    pub fn sample_headers<R>(&self, iteration: u32, rng: &mut R) -> Vec<(String, String)>
+    where
+        R: Rng,
+    {
+        let mut headers: Vec<_> = self
+            .headers
+            .iter()
+            .map(|h| (h.name.clone(), sample_header_value(&h.value, iteration, rng)))
+            .collect();
+        if let Some(host) = &self.host_header {
+            // hyper only derives the header from the URI if it is missing
+            headers.push((HOST.to_string(), sample_header_value(host, iteration, rng)));
+        }
+        headers
+    }
+
+    /// Randomly build the body of an HTTP request for this target,
+    /// if the target has a body generator and its method accepts a body.
+    pub fn sample_body<R>(&self, iteration: u32, rng: &mut R) -> Option<String>
+    where
+        R: Rng,
+    {
+        match self.method {
+            Method::Post | Method::Put | Method::Patch => {
+                self.body.as_ref().map(|generator| generator.sample(iteration, rng))
+            }
+            _ => None,
+        }
+    }
+
+    /// Percent-encode a sampled value for inclusion in the URI,
+    /// unless the target asks for raw values.
+    fn encode<'a>(&self, value: &'a str, set: &'static AsciiSet) -> Cow<'a, str> {
+        if self.raw {
+            Cow::Borrowed(value)
+        } else {
+            // values with nothing to encode are not copied
+            utf8_percent_encode(value, set).into()
+        }
+    }
+
+    /// Randomly build an HTTP request in order to test this target,
+    /// as the given iteration of the test.
+    pub fn sample<R>(&self, base_url: &str, iteration: u32, rng: &mut R) -> Result<Uri, InvalidUri>
     where
         R: Rng,
     {
         use TestArg::*;
         let mut uri = base_url.to_string();
-        if !self.endpoint.starts_with("/") {
+        let path = self.path();
+        if !path.starts_with('/') {
             uri.push('/');
         }
-        uri.push_str(&self.endpoint);
+        uri.push_str(&path);
+        // the query string is built separately, as it follows all path segments
         let mut qs = String::new();
         for arg in &self.args {
             match arg {
-                Path { generator } => {
+                Path { generator, multi_segment } => {
+                    let set = if *multi_segment { PATH_SEGMENTS } else { PATH_SEGMENT };
                     uri.push('/');
-                    uri.push_str(&generator.sample(rng));
+                    uri.push_str(&self.encode(&generator.sample(iteration, rng), set));
                 }
-                QueryString { name, value } => {
-                    if qs.is_empty() {
-                        qs.push('?');
-                    } else {
-                        qs.push('&');
-                    }
-                    qs.push_str(&name.sample(rng));
-                    let val = value.sample(rng);
+                Matrix { name, value } => {
+                    uri.push(';');
+                    uri.push_str(&self.encode(&name.sample(iteration, rng), MATRIX_PARAM));
+                    let val = value.sample(iteration, rng);
                     if !val.is_empty() {
-                        qs.push('=');
-                        qs.push_str(&val);
+                        uri.push('=');
+                        uri.push_str(&self.encode(&val, MATRIX_PARAM));
+                    }
+                }
+                QueryString {
+                    name,
+                    value,
+                    repeat,
+                    nested,
+                    optional,
+                    presence_chance,
+                } => {
+                    if *optional || presence_chance.is_some() {
+                        let chance = presence_chance.unwrap_or(DEFAULT_PRESENCE_CHANCE);
+                        if !rng.gen_bool(chance) {
+                            continue;
+                        }
+                    }
+                    // the same name is used in all repetitions
+                    let name = name.sample(iteration, rng);
+                    let name = self.encode(&name, QUERY_COMPONENT);
+                    let count = match repeat {
+                        Some(repeat) => repeat
+                            .sample(iteration, rng)
+                            .parse::<i64>()
+                            .map(|n| n.clamp(0, MAX_QUERY_REPEAT))
+                            .unwrap_or(1),
+                        None => 1,
+                    };
+                    for _ in 0..count {
+                        if qs.is_empty() {
+                            qs.push('?');
+                        } else {
+                            qs.push('&');
+                        }
+                        qs.push_str(&name);
+                        if let Some(nested) = nested {
+                            qs.push('[');
+                            qs.push_str(&self.encode(&nested.sample(iteration, rng), QUERY_COMPONENT));
+                            qs.push(']');
+                        }
+                        let val = value.sample(iteration, rng);
+                        if !val.is_empty() {
+                            qs.push('=');
+                            qs.push_str(&self.encode(&val, QUERY_COMPONENT));
+                        }
                     }
                 }
             }
         }
-        format!("{}{}", uri, qs).parse()
+        for (name, value) in &self.fixed_query {
+            qs.push(if qs.is_empty() { '?' } else { '&' });
+            qs.push_str(&self.encode(name, QUERY_COMPONENT));
+            qs.push('=');
+            qs.push_str(&self.encode(value, QUERY_COMPONENT));
+        }
+        uri.push_str(&qs);
+        uri.parse()
+    }
+}
+
+/// A pattern of HTTP response status codes
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum StatusPattern {
+    /// A single status code
+    Code(u16),
+    /// An inclusive range of status codes
+    Range { low: u16, high: u16 },
+}
+
+impl StatusPattern {
+    pub fn matches(&self, status: StatusCode) -> bool {
+        let status = status.as_u16();
+        match *self {
+            StatusPattern::Code(code) => status == code,
+            StatusPattern::Range { low, high } => low <= status && status <= high,
+        }
     }
 }
 
@@ -97,7 +544,11 @@ pub enum TestArg {
     #[serde(rename = "path")]
     Path {
         #[serde(default)]
-        generator: ArgGenerator
+        generator: ArgGenerator,
+        /// whether the value may span several path segments,
+        /// keeping its `/` as they are
+        #[serde(default)]
+        multi_segment: bool,
     },
     /// query string component
     #[serde(rename = "query")]
@@ -106,9 +557,117 @@ pub enum TestArg {
         name: ArgGenerator,
         #[serde(default)]
         value: ArgGenerator,
+        /// the number of times to include the parameter, each with a new
+        /// value (sampled as an integer, once if omitted or not a number)
+        #[serde(default)]
+        repeat: Option<Box<ArgGenerator>>,
+        /// the nested name in bracket notation, as in `name[nested]=value`
+        /// (an empty nested name builds an array, as in `name[]=value`)
+        #[serde(default)]
+        nested: Option<Box<ArgGenerator>>,
+        /// whether the parameter is only included in some of the requests
+        #[serde(default)]
+        optional: bool,
+        /// the probability of including an optional parameter in a request
+        /// (0.5 if omitted, and the parameter is optional if given)
+        #[serde(default)]
+        presence_chance: Option<f64>,
+    },
+    /// matrix parameter of the last path segment, as in `/items;name=value`
+    #[serde(rename = "matrix")]
+    Matrix {
+        #[serde(default)]
+        name: ArgGenerator,
+        #[serde(default)]
+        value: ArgGenerator,
     },
 }
 
+impl TestArg {
+    /// Check the argument's generators for problems.
+    pub fn validate(&self) -> Vec<String> {
+        match self {
+            TestArg::Path { generator, .. } => {
+                let mut problems = generator.validate();
+                problems.extend(check_uri_len(generator));
+                problems
+            }
+            TestArg::QueryString {
+                name,
+                value,
+                repeat,
+                nested,
+                presence_chance,
+                ..
+            } => {
+                let mut problems = name.validate();
+                if let Some(chance) = presence_chance.filter(|c| !(0. ..=1.).contains(c)) {
+                    problems.push(format!("presence_chance {} must be between 0 and 1", chance));
+                }
+                problems.extend(value.validate());
+                for g in repeat.iter().chain(nested) {
+                    problems.extend(g.validate());
+                }
+                let in_uri = std::iter::once(name).chain(Some(value)).chain(nested.as_deref());
+                problems.extend(in_uri.filter_map(check_uri_len));
+                problems
+            }
+            TestArg::Matrix { name, value } => {
+                let mut problems = name.validate();
+                problems.extend(value.validate());
+                problems.extend([name, value].iter().copied().filter_map(check_uri_len));
+                problems
+            }
+        }
+    }
+
+    /// Validate and prepare the argument's generators for sampling.
+    pub fn prepare(&mut self) -> Result<(), DynError> {
+        match self {
+            TestArg::Path { generator, .. } => generator.prepare(),
+            TestArg::QueryString {
+                name,
+                value,
+                repeat,
+                nested,
+                ..
+            } => {
+                name.prepare()?;
+                value.prepare()?;
+                repeat.iter_mut().chain(nested).try_for_each(|g| g.prepare())
+            }
+            TestArg::Matrix { name, value } => {
+                name.prepare()?;
+                value.prepare()
+            }
+        }
+    }
+
+    /// Visit the `captured` generators of this argument, by name.
+    pub fn visit_captured(&mut self, f: &mut dyn FnMut(&str, &mut CapturedValues)) {
+        match self {
+            TestArg::Path { generator, .. } => generator.visit_captured(f),
+            TestArg::QueryString {
+                name,
+                value,
+                repeat,
+                nested,
+                ..
+            } => {
+                name.visit_captured(f);
+                value.visit_captured(f);
+                for g in repeat.iter_mut().chain(nested) {
+                    g.visit_captured(f);
+                }
+            }
+            TestArg::Matrix { name, value } => {
+                name.visit_captured(f);
+                value.visit_captured(f);
+            }
+        }
+    }
+}
+
 /// The criterion of argument generation
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 #[serde(tag = "type")]
@@ -119,23 +678,424 @@ pub enum ArgGenerator<V = String> {
     /// Choose one of the given arguments at random
     #[serde(rename = "choice")]
     Choice { values: Vec<V> },
+    /// Choose one of the given arguments at random,
+    /// in proportion to their weights
+    #[serde(rename = "weighted_choice")]
+    WeightedChoice { values: Vec<WeightedValue<V>> },
     /// Choose a random number from the given range
     #[serde(rename = "range")]
     IntRange { low: i64, high: i64 },
+    /// Choose a value around the bounds of the given range with high
+    /// probability, or a random number from the range otherwise
+    #[serde(rename = "boundary")]
+    Boundary { low: i64, high: i64 },
+    /// Build a sequence of numbers across iterations of the test:
+    /// `start`, `start + step`, `start + 2 * step`, and so on
+    #[serde(rename = "sequence")]
+    Sequence {
+        #[serde(default)]
+        start: i64,
+        #[serde(default = "default_step")]
+        step: i64,
+    },
+    /// Choose a random decimal number from the given range,
+    /// with the exact bounds chosen more often
+    #[serde(rename = "float_range")]
+    FloatRange {
+        low: f64,
+        high: f64,
+        /// the number of decimal places (default is full precision)
+        #[serde(default)]
+        precision: Option<usize>,
+    },
+    /// Choose a random date from the given range, formatted with the given
+    /// `strftime`-like format string. Edge dates (the bounds of the range,
+    /// leap days, and the first and last days of a year) are chosen more often.
+    #[serde(rename = "date")]
+    Date {
+        #[serde(default = "default_date_format")]
+        format: String,
+        /// the earliest date, as `YYYY-MM-DD`
+        #[serde(default = "default_date_from")]
+        from: NaiveDate,
+        /// the latest date, as `YYYY-MM-DD`
+        #[serde(default = "default_date_to")]
+        to: NaiveDate,
+    },
+    /// Choose a random Unix timestamp in seconds from the given range,
+    /// with the bounds and other notable timestamps in it chosen more often
+    #[serde(rename = "epoch")]
+    Epoch {
+        #[serde(default)]
+        low: i64,
+        #[serde(default = "default_epoch_high")]
+        high: i64,
+    },
     /// Build a numeric sequence with the given length
+    /// (or a random length within a range)
     #[serde(rename = "numeric")]
-    Numeric { len: u32 },
+    Numeric { len: Length },
     /// Build an alphanumeric sequence with the given length
+    /// (or a random length within a range)
     #[serde(rename = "alphanumeric")]
-    AlphaNumeric { len: u32 },
+    AlphaNumeric { len: Length },
+    /// Build a percent-encoded sequence of random Unicode characters
+    /// with the given length, such as control characters, combining marks,
+    /// bidirectional overrides and emoji
+    #[serde(rename = "unicode")]
+    Unicode { len: u32 },
+    /// Build a random email address, either plausibly valid or
+    /// deliberately malformed (unless only valid addresses are requested)
+    #[serde(rename = "email")]
+    Email {
+        #[serde(default)]
+        valid_only: bool,
+    },
+    /// Build a random version 4 UUID
+    #[serde(rename = "uuid")]
+    Uuid {
+        #[serde(default = "default_true")]
+        hyphens: bool,
+    },
+    /// Build a random IPv4 (or IPv6) address, with edge cases such as
+    /// `0.0.0.0` more often, optionally with a CIDR prefix length, and
+    /// sometimes deliberately malformed if requested
+    #[serde(rename = "ip")]
+    IpAddr {
+        #[serde(default)]
+        v6: bool,
+        #[serde(default)]
+        cidr: bool,
+        #[serde(default)]
+        malformed: bool,
+    },
+    /// Choose one of the built-in payloads of the given kind of attack,
+    /// such as SQL injection or path traversal. In a header, payloads
+    /// which cannot be sent in one (such as `\nid`) are sampled again.
+    #[serde(rename = "attack")]
+    Attack { category: AttackKind },
+    /// Build a fragment which breaks the structure of a JSON document when
+    /// embedded in one, such as `"},{"` or unbalanced brackets
+    #[serde(rename = "json_fragment")]
+    JsonFragment,
+    /// Build a fragment which breaks the structure of an XML document when
+    /// embedded in one, such as entities, CDATA breakers or unbalanced tags
+    #[serde(rename = "xml_fragment")]
+    XmlFragment,
+    /// Choose one of the many spellings of true and false,
+    /// such as `1`, `yes`, `on` or `TRUE`
+    #[serde(rename = "bool")]
+    Bool,
+    /// Choose one of the many spellings of a missing value,
+    /// such as `null`, `nil`, `None` or the empty string
+    #[serde(rename = "nullish")]
+    Nullish,
+    /// Apply the given number of random mutations (such as bit flips,
+    /// insertions or deletions of characters) to a valid value
+    #[serde(rename = "mutate")]
+    Mutate { seed: String, mutations: u32 },
+    /// Build a random string matching the given regular expression
+    #[serde(rename = "regex")]
+    Regex {
+        pattern: String,
+        /// the regular expression, compiled on preparation
+        #[serde(skip)]
+        compiled: Option<CompiledRegex>,
+    },
+    /// Choose one of the lines in the given file at random
+    #[serde(rename = "wordlist")]
+    Wordlist {
+        path: PathBuf,
+        /// the non-empty lines of the file, loaded on preparation
+        #[serde(skip)]
+        lines: Vec<String>,
+    },
+    /// Build a JSON document from the given template, in which objects of
+    /// the form `{"$generator": <generator>}` are replaced by a sampled
+    /// string. With `"$raw": true`, the sampled value is placed in the
+    /// document as is instead (such as for numbers).
+    #[serde(rename = "json")]
+    Json {
+        template: Value,
+        /// the template with its generators, built on preparation
+        #[serde(skip)]
+        fields: JsonTemplate,
+    },
+    /// Concatenate the given literal strings and sampled values, in order
+    #[serde(rename = "template")]
+    Template { parts: Vec<TemplatePart<V>> },
+    /// Choose a line of the given file at random, reading only a part of the
+    /// file on each sample (for files too big to be loaded). The lines are
+    /// indexed on preparation, keeping the position of one in every
+    /// `LINE_INDEX_STEP`, so that all lines are equally likely.
+    #[serde(rename = "wordlist_streaming")]
+    WordlistStreaming {
+        path: PathBuf,
+        /// the index of the lines of the file, built on preparation
+        #[serde(skip)]
+        index: Option<LineIndex>,
+    },
     /// Choose one of the given generators at random (OR)
     #[serde(rename = "union")]
     Union { generators: Vec<ArgGenerator<V>> },
+    /// Sample the given generator, or provide an empty value with the given
+    /// probability (also written as `empty_chance` in any generator)
+    #[serde(rename = "maybe_empty")]
+    MaybeEmpty {
+        empty_chance: f64,
+        generator: Box<ArgGenerator<V>>,
+    },
+    /// Choose one of the values captured by earlier targets under the given
+    /// name at random (empty if none were captured)
+    #[serde(rename = "captured")]
+    Captured {
+        name: String,
+        /// the values captured so far, linked on preparation of the configuration
+        #[serde(skip)]
+        values: CapturedValues,
+    },
+    /// Take the value of the given environment variable (such as a secret
+    /// token which should not be written in the configuration), or the
+    /// default value if the variable is not set
+    #[serde(rename = "env")]
+    Env {
+        var: String,
+        #[serde(default)]
+        default: Option<String>,
+        /// the value of the variable, read on preparation
+        #[serde(skip)]
+        value: String,
+    },
     /// Generic "try multiple random things", easy to use
     #[serde(rename = "magic")]
     Magic,
 }
 
+/// A part of a template generator
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum TemplatePart<V = String> {
+    /// A literal string
+    Literal(String),
+    /// A sampled value
+    Generator(ArgGenerator<V>),
+}
+
+/// A JSON document template with generated parts
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonTemplate {
+    /// A fixed value
+    Value(Value),
+    /// A generated value, either as a JSON string or placed as is
+    Generated { generator: Box<ArgGenerator>, raw: bool },
+    Array(Vec<JsonTemplate>),
+    Object(Vec<(String, JsonTemplate)>),
+}
+
+impl Default for JsonTemplate {
+    fn default() -> Self {
+        JsonTemplate::Value(Value::Null)
+    }
+}
+
+impl JsonTemplate {
+    /// Build a template from a JSON value, preparing its generators.
+    pub fn from_value(value: &Value) -> Result<Self, DynError> {
+        match value {
+            Value::Object(map) if map.contains_key("$generator") => {
+                let mut generator: ArgGenerator = serde_json::from_value(map["$generator"].clone())
+                    .map_err(|e| format!("invalid generator in JSON template: {}", e))?;
+                generator.prepare()?;
+                let raw = map.get("$raw").and_then(Value::as_bool).unwrap_or(false);
+                Ok(JsonTemplate::Generated {
+                    generator: Box::new(generator),
+                    raw,
+                })
+            }
+            Value::Object(map) => Ok(JsonTemplate::Object(
+                map.iter()
+                    .map(|(k, v)| Ok((k.clone(), JsonTemplate::from_value(v)?)))
+                    .collect::<Result<_, DynError>>()?,
+            )),
+            Value::Array(values) => Ok(JsonTemplate::Array(
+                values
+                    .iter()
+                    .map(JsonTemplate::from_value)
+                    .collect::<Result<_, _>>()?,
+            )),
+            value => Ok(JsonTemplate::Value(value.clone())),
+        }
+    }
+
+    /// Visit the `captured` generators of the template, by name.
+    pub fn visit_captured(&mut self, f: &mut dyn FnMut(&str, &mut CapturedValues)) {
+        match self {
+            JsonTemplate::Value(_) => {}
+            JsonTemplate::Generated { generator, .. } => generator.visit_captured(f),
+            JsonTemplate::Array(items) => items.iter_mut().for_each(|t| t.visit_captured(f)),
+            JsonTemplate::Object(fields) => fields.iter_mut().for_each(|(_, t)| t.visit_captured(f)),
+        }
+    }
+
+    /// Render the template into JSON text, sampling its generated values.
+    pub fn render<R>(&self, iteration: u32, rng: &mut R, out: &mut String)
+    where
+        R: Rng,
+    {
+        match self {
+            JsonTemplate::Value(value) => out.push_str(&value.to_string()),
+            JsonTemplate::Generated { generator, raw } => {
+                let value = generator.sample(iteration, rng);
+                if *raw {
+                    out.push_str(&value);
+                } else {
+                    out.push_str(&Value::String(value).to_string());
+                }
+            }
+            JsonTemplate::Array(values) => {
+                out.push('[');
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    value.render(iteration, rng, out);
+                }
+                out.push(']');
+            }
+            JsonTemplate::Object(fields) => {
+                out.push('{');
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    out.push_str(&Value::String(key.clone()).to_string());
+                    out.push(':');
+                    value.render(iteration, rng, out);
+                }
+                out.push('}');
+            }
+        }
+    }
+}
+
+/// The length of a generated value: either fixed,
+/// or chosen at random from a range (bounds included)
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Length {
+    Fixed(u32),
+    Range { min: u32, max: u32 },
+}
+
+impl Length {
+    /// Obtain the length of a value.
+    pub fn sample<R>(self, rng: &mut R) -> usize
+    where
+        R: Rng,
+    {
+        match self {
+            Length::Fixed(len) => len as usize,
+            Length::Range { min, max } => rng.gen_range(min..=max) as usize,
+        }
+    }
+
+    /// The least length which can be sampled
+    pub fn min(self) -> usize {
+        match self {
+            Length::Fixed(len) => len as usize,
+            Length::Range { min, .. } => min as usize,
+        }
+    }
+}
+
+/// Maximum length of a request URI in bytes, as imposed by the `http` crate
+pub const MAX_URI_LEN: usize = 65534;
+
+/// Check whether the values of a generator used in a URI can fit in it.
+fn check_uri_len(generator: &ArgGenerator) -> Option<String> {
+    let len = generator.min_len();
+    if len > MAX_URI_LEN {
+        Some(format!(
+            "{}: len {} does not fit in a request URI (at most {} bytes), use it in the body or a header instead",
+            generator.name(),
+            len,
+            MAX_URI_LEN
+        ))
+    } else {
+        None
+    }
+}
+
+/// A kind of attack, with a built-in list of payloads
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttackKind {
+    /// SQL injection
+    Sqli,
+    /// cross-site scripting
+    Xss,
+    /// path traversal
+    PathTraversal,
+    /// shell command injection
+    CommandInjection,
+    /// format string injection
+    FormatString,
+    /// XML external entities
+    Xxe,
+}
+
+impl AttackKind {
+    /// The payloads of this kind of attack
+    pub fn payloads(self) -> &'static [&'static str] {
+        match self {
+            AttackKind::Sqli => &SQLI_PAYLOADS,
+            AttackKind::Xss => &XSS_PAYLOADS,
+            AttackKind::PathTraversal => &PATH_TRAVERSAL_PAYLOADS,
+            AttackKind::CommandInjection => &COMMAND_INJECTION_PAYLOADS,
+            AttackKind::FormatString => &FORMAT_STRING_PAYLOADS,
+            AttackKind::Xxe => &XXE_PAYLOADS,
+        }
+    }
+}
+
+/// A value of a weighted choice
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct WeightedValue<V = String> {
+    pub value: V,
+    /// the relative likelihood of choosing this value
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+fn default_step() -> i64 {
+    1
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_date_format() -> String {
+    "%Y-%m-%d".to_string()
+}
+
+fn default_date_from() -> NaiveDate {
+    NaiveDate::from_ymd_opt(1970, 1, 1).unwrap()
+}
+
+fn default_date_to() -> NaiveDate {
+    NaiveDate::from_ymd_opt(2099, 12, 31).unwrap()
+}
+
+/// The last second representable by a signed 32-bit Unix timestamp
+fn default_epoch_high() -> i64 {
+    i64::from(i32::MAX)
+}
+
 /// The default is magic :)
 impl<V> Default for ArgGenerator<V> {
     fn default() -> Self {
@@ -147,8 +1107,210 @@ impl<V> ArgGenerator<V>
 where
     V: std::fmt::Display,
 {
-    /// Randomly sample a value for use in the test.
-    pub fn sample<R>(&self, rng: &mut R) -> String
+    /// Check the generator for problems which would prevent sampling,
+    /// returning a description of each problem found.
+    pub fn validate(&self) -> Vec<String> {
+        use ArgGenerator::*;
+        let mut problems = Vec::new();
+        match self {
+            WeightedChoice { values }
+                if values.iter().any(|v| !(v.weight >= 0. && v.weight.is_finite())) =>
+            {
+                problems.push("weighted_choice: weights must be finite and not negative".to_string());
+            }
+            IntRange { low, high } | Boundary { low, high } if low > high => {
+                problems.push(format!("{}: low {} is greater than high {}", self.name(), low, high));
+            }
+            FloatRange { low, high, .. } => {
+                if !(low.is_finite() && high.is_finite()) {
+                    problems.push("float_range: bounds must be finite".to_string());
+                } else if low > high {
+                    problems.push(format!("float_range: low {} is greater than high {}", low, high));
+                }
+            }
+            Date { format, from, to } => {
+                // also rejects time fields, which dates do not have
+                let mut out = String::new();
+                if write!(out, "{}", from.format(format)).is_err() {
+                    problems.push(format!("date: invalid format '{}'", format));
+                }
+                if from > to {
+                    problems.push(format!("date: {} is after {}", from, to));
+                }
+            }
+            Epoch { low, high } if low > high => {
+                problems.push(format!("epoch: low {} is greater than high {}", low, high));
+            }
+            Numeric { len } | AlphaNumeric { len } => match len {
+                Length::Fixed(0) => {
+                    problems.push(format!("{}: len must be greater than 0", self.name()));
+                }
+                Length::Range { min, max } if min > max => {
+                    problems.push(format!(
+                        "{}: min len {} is greater than max len {}",
+                        self.name(),
+                        min,
+                        max
+                    ));
+                }
+                _ => {}
+            },
+            Unicode { len } if *len == 0 => {
+                problems.push(format!("{}: len must be greater than 0", self.name()));
+            }
+            Regex { pattern, .. } => {
+                if let Err(e) = compile_regex(pattern) {
+                    problems.push(format!("regex: {}", e));
+                }
+            }
+            Wordlist { path, .. } | WordlistStreaming { path, .. } if !path.is_file() => {
+                problems.push(format!("{}: file '{}' not found", self.name(), path.display()));
+            }
+            Json { template, .. } => validate_json_template(template, &mut problems),
+            Template { parts } => {
+                for part in parts {
+                    if let TemplatePart::Generator(g) = part {
+                        problems.extend(g.validate());
+                    }
+                }
+            }
+            Union { generators } => {
+                for g in generators {
+                    problems.extend(g.validate());
+                }
+            }
+            MaybeEmpty { empty_chance, generator } => {
+                if !(0. ..=1.).contains(empty_chance) {
+                    problems.push(format!("empty_chance {} must be between 0 and 1", empty_chance));
+                }
+                problems.extend(generator.validate());
+            }
+            Env { var, default, .. } => {
+                if let Err(e) = env_value(var, default.as_deref()) {
+                    problems.push(format!("env: {}", e));
+                }
+            }
+            Mutate { mutations, .. } if *mutations > MAX_MUTATIONS => {
+                problems.push(format!("mutate: mutations must be at most {}", MAX_MUTATIONS));
+            }
+            _ => {}
+        }
+        problems
+    }
+
+    /// The name of the generator's type, as in the configuration
+    pub fn name(&self) -> &'static str {
+        use ArgGenerator::*;
+        match self {
+            Fixed { .. } => "fixed",
+            Choice { .. } => "choice",
+            WeightedChoice { .. } => "weighted_choice",
+            IntRange { .. } => "range",
+            Boundary { .. } => "boundary",
+            Sequence { .. } => "sequence",
+            FloatRange { .. } => "float_range",
+            Date { .. } => "date",
+            Epoch { .. } => "epoch",
+            Numeric { .. } => "numeric",
+            AlphaNumeric { .. } => "alphanumeric",
+            Unicode { .. } => "unicode",
+            Email { .. } => "email",
+            Uuid { .. } => "uuid",
+            IpAddr { .. } => "ip",
+            Attack { .. } => "attack",
+            Regex { .. } => "regex",
+            Wordlist { .. } => "wordlist",
+            WordlistStreaming { .. } => "wordlist_streaming",
+            Json { .. } => "json",
+            Template { .. } => "template",
+            Union { .. } => "union",
+            MaybeEmpty { .. } => "maybe_empty",
+            Captured { .. } => "captured",
+            Env { .. } => "env",
+            JsonFragment => "json_fragment",
+            XmlFragment => "xml_fragment",
+            Bool => "bool",
+            Nullish => "nullish",
+            Mutate { .. } => "mutate",
+            Magic => "magic",
+        }
+    }
+
+    /// The least length of the values sampled, in bytes
+    /// (0 if it is not known in advance)
+    pub fn min_len(&self) -> usize {
+        use ArgGenerator::*;
+        match self {
+            Numeric { len } | AlphaNumeric { len } => len.min(),
+            Unicode { len } => *len as usize,
+            _ => 0,
+        }
+    }
+
+    /// Validate and prepare the generator for sampling.
+    pub fn prepare(&mut self) -> Result<(), DynError> {
+        use ArgGenerator::*;
+        match self {
+            Regex { pattern, compiled } => {
+                *compiled = Some(CompiledRegex(compile_regex(pattern)?));
+                Ok(())
+            }
+            Wordlist { path, lines } => {
+                let text = read_to_string(&path).map_err(|e| {
+                    format!("could not read wordlist '{}': {}", path.display(), e)
+                })?;
+                *lines = text
+                    .lines()
+                    .filter(|l| !l.is_empty())
+                    .map(|l| l.to_string())
+                    .collect();
+                Ok(())
+            }
+            WordlistStreaming { path, index } => {
+                *index = Some(LineIndex::build(path).map_err(|e| {
+                    format!("could not read wordlist '{}': {}", path.display(), e)
+                })?);
+                Ok(())
+            }
+            Json { template, fields } => {
+                *fields = JsonTemplate::from_value(template)?;
+                Ok(())
+            }
+            Template { parts } => parts.iter_mut().try_for_each(|part| match part {
+                TemplatePart::Literal(_) => Ok(()),
+                TemplatePart::Generator(g) => g.prepare(),
+            }),
+            Union { generators } => generators.iter_mut().try_for_each(|g| g.prepare()),
+            MaybeEmpty { generator, .. } => generator.prepare(),
+            Env { var, default, value } => {
+                *value = env_value(var, default.as_deref())?;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Visit the `captured` generators within this generator, by name.
+    pub fn visit_captured(&mut self, f: &mut dyn FnMut(&str, &mut CapturedValues)) {
+        use ArgGenerator::*;
+        match self {
+            Captured { name, values } => f(name, values),
+            Template { parts } => {
+                for part in parts {
+                    if let TemplatePart::Generator(g) = part {
+                        g.visit_captured(f);
+                    }
+                }
+            }
+            Union { generators } => generators.iter_mut().for_each(|g| g.visit_captured(f)),
+            MaybeEmpty { generator, .. } => generator.visit_captured(f),
+            Json { fields, .. } => fields.visit_captured(f),
+            _ => {}
+        }
+    }
+
+    /// Randomly sample a value for use in the given iteration of the test.
+    pub fn sample<R>(&self, iteration: u32, rng: &mut R) -> String
     where
         R: Rng,
     {
@@ -157,28 +1319,743 @@ where
             Fixed { value } => value.to_string(),
             Choice { values } if values.is_empty() => "".to_string(),
             Union { generators } if generators.is_empty() => "".to_string(),
-            Union { generators } => generators.choose(rng).unwrap().sample(rng),
+            Wordlist { lines, .. } if lines.is_empty() => "".to_string(),
+            Union { generators } => generators.choose(rng).unwrap().sample(iteration, rng),
+            MaybeEmpty { empty_chance, generator } => {
+                if rng.gen_bool(*empty_chance) {
+                    String::new()
+                } else {
+                    generator.sample(iteration, rng)
+                }
+            }
+            Captured { values, .. } => values.choose(rng),
+            Env { value, .. } => value.clone(),
+            Template { parts } => parts
+                .iter()
+                .map(|part| match part {
+                    TemplatePart::Literal(text) => text.clone(),
+                    TemplatePart::Generator(g) => g.sample(iteration, rng),
+                })
+                .collect(),
             Choice { values } => values.choose(rng).unwrap().to_string(),
+            WeightedChoice { values } => {
+                // no values or no positive weights, nothing to choose from
+                match WeightedIndex::new(values.iter().map(|v| v.weight)) {
+                    Ok(dist) => values[rng.sample(dist)].value.to_string(),
+                    Err(_) => "".to_string(),
+                }
+            }
+            Wordlist { lines, .. } => lines.choose(rng).unwrap().to_string(),
             IntRange { low, high } => rng.gen_range(*low ..= *high).to_string(),
-            Numeric { len } => std::iter::repeat_with(|| rng.gen_range(b'0' ..= b'9') as char)
-                .take(*len as usize)
-                .collect(),
-            AlphaNumeric { len } => std::iter::repeat_with(|| {
-                rng.sample(rand::distributions::Alphanumeric)
-            }).take(*len as usize)
-                .map(|c| c as char)
-                .collect(),
+            Boundary { low, high } => {
+                if rng.gen_bool(0.8) {
+                    let edges = [
+                        *low,
+                        low.saturating_sub(1),
+                        *high,
+                        high.saturating_add(1),
+                        0,
+                        i64::MAX,
+                        i64::MIN,
+                    ];
+                    edges.choose(rng).unwrap().to_string()
+                } else {
+                    rng.gen_range(*low..=*high).to_string()
+                }
+            }
+            Sequence { start, step } => start
+                .wrapping_add(step.wrapping_mul(i64::from(iteration)))
+                .to_string(),
+            FloatRange { low, high, precision } => {
+                let x = match rng.gen_range(0..10) {
+                    0 => *low,
+                    1 => *high,
+                    _ => rng.gen_range(*low..=*high),
+                };
+                match precision {
+                    Some(precision) => format!("{:.*}", precision, x),
+                    None => x.to_string(),
+                }
+            }
+            Date { format, from, to } => {
+                let days = (*to - *from).num_days();
+                let date = *from + DateDuration::days(rng.gen_range(0..=days));
+                let date = if rng.gen_bool(0.2) {
+                    let year = date.year();
+                    let leap_year = (year..year + 8)
+                        .find(|y| NaiveDate::from_ymd_opt(*y, 2, 29).is_some())
+                        .unwrap_or(year);
+                    let edges = [
+                        Some(*from),
+                        Some(*to),
+                        NaiveDate::from_ymd_opt(leap_year, 2, 29),
+                        NaiveDate::from_ymd_opt(year, 1, 1),
+                        NaiveDate::from_ymd_opt(year, 12, 31),
+                    ];
+                    edges
+                        .iter()
+                        .flatten()
+                        .filter(|d| from <= *d && *d <= to)
+                        .copied()
+                        .collect::<Vec<_>>()
+                        .choose(rng)
+                        .copied()
+                        .unwrap_or(date)
+                } else {
+                    date
+                };
+                date.format(format).to_string()
+            }
+            Epoch { low, high } => {
+                if rng.gen_bool(0.2) {
+                    let edges = [0, -1, i64::from(i32::MAX), i64::from(i32::MAX) + 1];
+                    // the notable timestamps are only chosen within the range
+                    let edges: Vec<_> = [*low, *high]
+                        .iter()
+                        .chain(edges.iter().filter(|t| (*low..=*high).contains(*t)))
+                        .copied()
+                        .collect();
+                    edges.choose(rng).unwrap().to_string()
+                } else {
+                    rng.gen_range(*low..=*high).to_string()
+                }
+            }
+            Numeric { len } => {
+                let len = len.sample(rng);
+                let digits = rand::distributions::Uniform::new_inclusive(b'0', b'9');
+                random_ascii(rng, digits, len)
+            }
+            AlphaNumeric { len } => {
+                let len = len.sample(rng);
+                random_alphanumeric(rng, len)
+            }
+            WordlistStreaming { index: Some(index), .. } => {
+                // a file which can no longer be read has no lines to choose from
+                index.random_line(rng).unwrap_or_default()
+            }
+            WordlistStreaming { index: None, .. } => String::new(),
+            Json { fields, .. } => {
+                let mut out = String::new();
+                fields.render(iteration, rng, &mut out);
+                out
+            }
+            Unicode { len } => {
+                let text: String = std::iter::repeat_with(|| random_char(rng))
+                    .take(*len as usize)
+                    .collect();
+                utf8_percent_encode(&text, NON_ALPHANUMERIC).to_string()
+            }
+            Email { valid_only } => {
+                if *valid_only || rng.gen_bool(0.5) {
+                    valid_email(rng)
+                } else {
+                    malformed_email(rng)
+                }
+            }
+            Attack { category } => category
+                .payloads()
+                .choose(rng)
+                .expect("There should be at least one payload")
+                .to_string(),
+            Uuid { hyphens } => {
+                let mut bytes: [u8; 16] = rng.gen();
+                // version 4, variant 1
+                bytes[6] = (bytes[6] & 0x0f) | 0x40;
+                bytes[8] = (bytes[8] & 0x3f) | 0x80;
+                let mut out = String::with_capacity(36);
+                for (i, b) in bytes.iter().enumerate() {
+                    if *hyphens && (i == 4 || i == 6 || i == 8 || i == 10) {
+                        out.push('-');
+                    }
+                    out.push_str(&format!("{:02x}", b));
+                }
+                out
+            }
+            IpAddr { v6, cidr, malformed } => {
+                if *malformed && rng.gen_bool(0.5) {
+                    malformed_ip(rng, *v6, *cidr)
+                } else {
+                    valid_ip(rng, *v6, *cidr)
+                }
+            }
+            JsonFragment => json_fragment(rng),
+            XmlFragment => xml_fragment(rng),
+            Bool => BOOL_VALUES.choose(rng).unwrap().to_string(),
+            Nullish => NULLISH_VALUES.choose(rng).unwrap().to_string(),
+            Mutate { seed, mutations } => mutate(seed, *mutations, rng),
+            Regex { compiled: Some(regex), .. } => rng.sample::<String, _>(&regex.0),
+            Regex { compiled: None, .. } => String::new(),
             Magic => {
                 let generators = [
                     Choice { values: vec!["", "false", "true", "null", "undefined", "NaN", "%20", "%27"] },
-                    AlphaNumeric { len: 16 },
+                    AlphaNumeric { len: Length::Fixed(16) },
                     IntRange { low: -100000, high: 100000 },
                 ];
 
                 generators.choose(rng)
                     .expect("There should be at least one generator")
-                    .sample(rng)
+                    .sample(iteration, rng)
             }
         }
     }
 }
+
+/// Maximum number of attempts at sampling a valid header value
+pub const MAX_HEADER_ATTEMPTS: u32 = 16;
+
+/// Sample a value for a header, until it is one which can be sent in a
+/// header (non-ASCII characters are sent as their UTF-8 bytes).
+fn sample_header_value<R>(generator: &ArgGenerator, iteration: u32, rng: &mut R) -> String
+where
+    R: Rng,
+{
+    let mut value = generator.sample(iteration, rng);
+    for _ in 1..MAX_HEADER_ATTEMPTS {
+        if HeaderValue::from_bytes(value.as_bytes()).is_ok() {
+            break;
+        }
+        value = generator.sample(iteration, rng);
+    }
+    value
+}
+
+/// Maximum number of attempts at finding a non-empty line in a wordlist
+const MAX_LINE_ATTEMPTS: u32 = 16;
+
+/// Number of lines from one indexed line of a streamed wordlist to the next
+pub const LINE_INDEX_STEP: u64 = 256;
+
+/// A sparse index of the lines of a file, keeping the file open, so that a
+/// random line is found by reading at most `LINE_INDEX_STEP` lines
+#[derive(Debug, Clone)]
+pub struct LineIndex {
+    /// the open file, shared by the clones of the generator
+    file: Arc<Mutex<BufReader<File>>>,
+    /// the position of every `LINE_INDEX_STEP`-th line, from the first one
+    offsets: Arc<Vec<u64>>,
+    /// the number of lines in the file
+    lines: u64,
+}
+
+/// As the index is built from the file, two are the same whenever they
+/// index the same lines.
+impl PartialEq for LineIndex {
+    fn eq(&self, other: &Self) -> bool {
+        self.lines == other.lines && self.offsets == other.offsets
+    }
+}
+
+impl LineIndex {
+    /// Index the lines of a file, reading it once.
+    fn build(path: &Path) -> std::io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut offsets = Vec::new();
+        let (mut lines, mut offset) = (0, 0);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let len = reader.read_until(b'\n', &mut line)?;
+            if len == 0 {
+                break;
+            }
+            if lines % LINE_INDEX_STEP == 0 {
+                offsets.push(offset);
+            }
+            lines += 1;
+            offset += len as u64;
+        }
+        Ok(LineIndex {
+            file: Arc::new(Mutex::new(reader)),
+            offsets: Arc::new(offsets),
+            lines,
+        })
+    }
+
+    /// Read a random non-empty line of the file, with all lines equally
+    /// likely to be chosen.
+    fn random_line<R>(&self, rng: &mut R) -> std::io::Result<String>
+    where
+        R: Rng,
+    {
+        if self.lines == 0 {
+            return Ok(String::new());
+        }
+        let mut reader = self.file.lock().unwrap();
+        let mut line = Vec::new();
+        for _ in 0..MAX_LINE_ATTEMPTS {
+            let n = rng.gen_range(0..self.lines);
+            reader.seek(SeekFrom::Start(self.offsets[(n / LINE_INDEX_STEP) as usize]))?;
+            // skip the lines from the indexed one to the chosen one
+            for _ in 0..n % LINE_INDEX_STEP {
+                line.clear();
+                reader.read_until(b'\n', &mut line)?;
+            }
+            line.clear();
+            reader.read_until(b'\n', &mut line)?;
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim_end_matches(&['\n', '\r'][..]);
+            if !text.is_empty() {
+                return Ok(text.to_string());
+            }
+        }
+        Ok(String::new())
+    }
+}
+
+/// Check the generators of a JSON template for problems.
+fn validate_json_template(value: &Value, problems: &mut Vec<String>) {
+    match value {
+        Value::Object(map) if map.contains_key("$generator") => {
+            match serde_json::from_value::<ArgGenerator>(map["$generator"].clone()) {
+                Ok(generator) => problems.extend(generator.validate()),
+                Err(e) => problems.push(format!("json: invalid generator: {}", e)),
+            }
+        }
+        Value::Object(map) => map.values().for_each(|v| validate_json_template(v, problems)),
+        Value::Array(values) => values.iter().for_each(|v| validate_json_template(v, problems)),
+        _ => {}
+    }
+}
+
+/// Unicode formatting characters which are known to cause trouble
+/// (zero width characters, bidirectional marks and overrides, and the BOM)
+const FORMAT_CHARS: [u32; 15] = [
+    0x200B, 0x200C, 0x200D, 0x200E, 0x200F, 0x202A, 0x202B, 0x202C, 0x202D, 0x202E, 0x2066,
+    0x2067, 0x2068, 0x2069, 0xFEFF,
+];
+
+/// Pick a random Unicode character from one of several troublesome classes.
+fn random_char<R>(rng: &mut R) -> char
+where
+    R: Rng,
+{
+    loop {
+        let code = match rng.gen_range(0..7) {
+            // C0 control characters and DEL
+            0 => *[rng.gen_range(0..0x20), 0x7F].choose(rng).unwrap(),
+            // C1 control characters and the rest of Latin-1
+            1 => rng.gen_range(0x80..=0xFF),
+            // combining diacritical marks
+            2 => rng.gen_range(0x300..=0x36F),
+            3 => *FORMAT_CHARS.choose(rng).unwrap(),
+            // emoji
+            4 => rng.gen_range(0x1F300..=0x1FAFF),
+            // anywhere in the basic multilingual plane
+            5 => rng.gen_range(0..=0xFFFF),
+            // anywhere in the astral planes
+            _ => rng.gen_range(0x10000..=0x10FFFF),
+        };
+        // surrogates are not characters, try again
+        if let Some(c) = std::char::from_u32(code) {
+            return c;
+        }
+    }
+}
+
+/// SQL injection payloads of the `attack` generator
+const SQLI_PAYLOADS: [&str; 12] = [
+    "'",
+    "\"",
+    "' OR '1'='1",
+    "' OR 1=1--",
+    "\" OR \"\"=\"",
+    "1; DROP TABLE users--",
+    "' UNION SELECT NULL--",
+    "' UNION SELECT NULL,NULL--",
+    "admin'--",
+    "1' AND SLEEP(5)--",
+    "1 AND 1=2",
+    "'; WAITFOR DELAY '0:0:5'--",
+];
+
+/// Cross-site scripting payloads of the `attack` generator
+const XSS_PAYLOADS: [&str; 10] = [
+    "<script>alert(1)</script>",
+    "\"><script>alert(1)</script>",
+    "<img src=x onerror=alert(1)>",
+    "<svg onload=alert(1)>",
+    "javascript:alert(1)",
+    "'\"><iframe src=javascript:alert(1)>",
+    "<body onload=alert(1)>",
+    "</textarea><script>alert(1)</script>",
+    "{{constructor.constructor('alert(1)')()}}",
+    "<a href=\"jav&#x09;ascript:alert(1)\">x</a>",
+];
+
+/// Path traversal payloads of the `attack` generator
+const PATH_TRAVERSAL_PAYLOADS: [&str; 10] = [
+    "../../etc/passwd",
+    "../../../../../../etc/passwd",
+    "..\\..\\..\\windows\\win.ini",
+    "/etc/passwd",
+    "....//....//etc/passwd",
+    "..%2f..%2fetc%2fpasswd",
+    "%2e%2e%2f%2e%2e%2fetc%2fpasswd",
+    "..%252f..%252fetc%252fpasswd",
+    "../../etc/passwd%00.png",
+    "C:\\Windows\\win.ini",
+];
+
+/// Shell command injection payloads of the `attack` generator
+const COMMAND_INJECTION_PAYLOADS: [&str; 10] = [
+    "; id",
+    "| id",
+    "|| id",
+    "&& id",
+    "`id`",
+    "$(id)",
+    "; sleep 5",
+    "& ping -n 5 127.0.0.1 &",
+    "\nid",
+    "; cat /etc/passwd",
+];
+
+/// Format string payloads of the `attack` generator
+const FORMAT_STRING_PAYLOADS: [&str; 8] = [
+    "%s%s%s%s%s",
+    "%x%x%x%x",
+    "%n%n%n%n",
+    "%p%p%p%p",
+    "%08x.%08x.%08x",
+    "%.1000000d",
+    "{0}{1}{2}",
+    "${jndi:ldap://127.0.0.1/a}",
+];
+
+/// XML external entity payloads of the `attack` generator
+const XXE_PAYLOADS: [&str; 4] = [
+    "<?xml version=\"1.0\"?><!DOCTYPE a [<!ENTITY x SYSTEM \"file:///etc/passwd\">]><a>&x;</a>",
+    "<?xml version=\"1.0\"?><!DOCTYPE a [<!ENTITY x SYSTEM \"http://127.0.0.1/\">]><a>&x;</a>",
+    "<?xml version=\"1.0\"?><!DOCTYPE a [<!ENTITY % x SYSTEM \"file:///etc/passwd\"> %x;]><a/>",
+    "<?xml version=\"1.0\"?><!DOCTYPE a [<!ENTITY b \"b\"><!ENTITY c \"&b;&b;&b;&b;&b;&b;&b;&b;\"><!ENTITY d \"&c;&c;&c;&c;&c;&c;&c;&c;\">]><a>&d;</a>",
+];
+
+/// Malformed or unusual JSON documents of the `json_fragment` generator
+const JSON_FRAGMENTS: [&str; 16] = [
+    "\"",
+    "\\",
+    "\"},{\"",
+    "\",\"admin\":true,\"",
+    "\"}]}",
+    "{",
+    "}",
+    "[",
+    "]",
+    "\":",
+    "\\u0000",
+    "\\ud800",
+    "\\x41",
+    "NaN",
+    "/*",
+    "\"\n}\n{\"",
+];
+
+/// Malformed or unusual XML documents of the `xml_fragment` generator
+const XML_FRAGMENTS: [&str; 16] = [
+    "<",
+    ">",
+    "&",
+    "&amp",
+    "&lt;",
+    "&undefined;",
+    "&#0;",
+    "&#xD800;",
+    "]]>",
+    "<![CDATA[",
+    "]]><a>",
+    "</a>",
+    "<a>",
+    "\"/><a b=\"",
+    "<!--",
+    "<?xml version=\"1.0\"?>",
+];
+
+/// Spellings of boolean values of the `bool` generator
+const BOOL_VALUES: [&str; 24] = [
+    "true", "false", "True", "False", "TRUE", "FALSE", "1", "0", "yes", "no", "Yes", "NO", "on",
+    "off", "ON", "Off", "y", "n", "t", "f", "-1", "2", " true", "true ",
+];
+
+/// Spellings of missing values of the `nullish` generator
+const NULLISH_VALUES: [&str; 16] = [
+    "", "null", "NULL", "Null", "nil", "None", "none", "undefined", "NaN", "nan", "void", "\\N",
+    "(null)", "<null>", "0", " ",
+];
+
+/// Top level domains of generated email addresses
+const EMAIL_TLDS: [&str; 6] = ["com", "org", "net", "io", "pt", "co.uk"];
+
+/// Edge case IPv4 addresses
+const IPV4_EDGES: [&str; 7] = [
+    "0.0.0.0",
+    "255.255.255.255",
+    "127.0.0.1",
+    "10.0.0.1",
+    "192.168.0.1",
+    "169.254.169.254",
+    "224.0.0.1",
+];
+
+/// Edge case IPv6 addresses
+const IPV6_EDGES: [&str; 7] = [
+    "::",
+    "::1",
+    "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff",
+    "fe80::1",
+    "::ffff:127.0.0.1",
+    "2001:db8::1",
+    "ff02::1",
+];
+
+/// Malformed IPv4 addresses
+const MALFORMED_IPV4: [&str; 10] = [
+    "256.0.0.1",
+    "1.2.3",
+    "1.2.3.4.5",
+    "01.02.03.04",
+    "1..2.3",
+    "-1.0.0.0",
+    "0x7f.0.0.1",
+    "2130706433",
+    "1.2.3.4 ",
+    "1.2.3.a",
+];
+
+/// Malformed IPv6 addresses
+const MALFORMED_IPV6: [&str; 8] = [
+    ":::",
+    "1::2::3",
+    "12345::1",
+    "gggg::1",
+    "::ffff:999.0.0.1",
+    "1:2:3:4:5:6:7:8:9",
+    "fe80::1%eth0",
+    "[::1]",
+];
+
+fn random_alphanumeric<R>(rng: &mut R, len: usize) -> String
+where
+    R: Rng,
+{
+    random_ascii(rng, rand::distributions::Alphanumeric, len)
+}
+
+/// Build a string of the given length from random ASCII characters,
+/// filling a buffer allocated once (values may be megabytes long).
+fn random_ascii<R, D>(rng: &mut R, chars: D, len: usize) -> String
+where
+    R: Rng,
+    D: Distribution<u8>,
+{
+    let mut buf = Vec::with_capacity(len);
+    buf.extend(rng.sample_iter(chars).take(len));
+    String::from_utf8(buf).expect("only ASCII characters are sampled")
+}
+
+/// Build a valid IP address, either an edge case or a random one.
+fn valid_ip<R>(rng: &mut R, v6: bool, cidr: bool) -> String
+where
+    R: Rng,
+{
+    let edge = rng.gen_bool(0.2);
+    let (mut ip, max_prefix) = match (v6, edge) {
+        (false, true) => (IPV4_EDGES.choose(rng).unwrap().to_string(), 32),
+        (false, false) => (Ipv4Addr::from(rng.gen::<u32>()).to_string(), 32),
+        (true, true) => (IPV6_EDGES.choose(rng).unwrap().to_string(), 128),
+        (true, false) => (Ipv6Addr::from(rng.gen::<u128>()).to_string(), 128),
+    };
+    if cidr {
+        write!(ip, "/{}", rng.gen_range(0..=max_prefix)).unwrap();
+    }
+    ip
+}
+
+/// Build a malformed IP address, or a valid one with an invalid
+/// CIDR prefix length.
+fn malformed_ip<R>(rng: &mut R, v6: bool, cidr: bool) -> String
+where
+    R: Rng,
+{
+    let max_prefix = if v6 { 128 } else { 32 };
+    if cidr && rng.gen_bool(0.5) {
+        let ip = valid_ip(rng, v6, false);
+        let prefix = match rng.gen_range(0..4) {
+            0 => (max_prefix + 1).to_string(),
+            1 => "-1".to_string(),
+            2 => "a".to_string(),
+            _ => String::new(),
+        };
+        return format!("{}/{}", ip, prefix);
+    }
+    if v6 {
+        MALFORMED_IPV6.choose(rng).unwrap().to_string()
+    } else if rng.gen_bool(0.5) {
+        MALFORMED_IPV4.choose(rng).unwrap().to_string()
+    } else {
+        // one of the octets is out of range
+        let mut octets: Vec<u32> = (0..4).map(|_| rng.gen_range(0..=255)).collect();
+        octets[rng.gen_range(0..4)] = rng.gen_range(256..=999);
+        octets.iter().map(|o| o.to_string()).collect::<Vec<_>>().join(".")
+    }
+}
+
+/// Build a fragment breaking the structure of a JSON document:
+/// one of the built-in fragments, or a run of unbalanced brackets.
+fn json_fragment<R>(rng: &mut R) -> String
+where
+    R: Rng,
+{
+    if rng.gen_bool(0.8) {
+        JSON_FRAGMENTS.choose(rng).unwrap().to_string()
+    } else {
+        let brackets = b"{}[]";
+        let len = rng.gen_range(1..=64);
+        (0..len).map(|_| *brackets.choose(rng).unwrap() as char).collect()
+    }
+}
+
+/// Build a fragment breaking the structure of an XML document:
+/// one of the built-in fragments, or a run of unclosed tags.
+fn xml_fragment<R>(rng: &mut R) -> String
+where
+    R: Rng,
+{
+    if rng.gen_bool(0.8) {
+        XML_FRAGMENTS.choose(rng).unwrap().to_string()
+    } else {
+        let depth = rng.gen_range(1..=64);
+        "<a>".repeat(depth)
+    }
+}
+
+/// Maximum number of mutations applied to each value of a `mutate` generator
+const MAX_MUTATIONS: u32 = 1024;
+
+/// Apply random mutations to a value, one character at a time: flipping a
+/// bit, replacing, inserting, deleting or duplicating a character, or
+/// swapping two adjacent ones. Inserted and replacing characters are
+/// printable ASCII, and bit flips which would make a control character are
+/// not applied, so that mutated values can still be sent in a header.
+fn mutate<R>(seed: &str, mutations: u32, rng: &mut R) -> String
+where
+    R: Rng,
+{
+    let mut chars: Vec<char> = seed.chars().collect();
+    for _ in 0..mutations {
+        if chars.is_empty() {
+            chars.push(rng.gen_range(b' '..=b'~') as char);
+            continue;
+        }
+        let i = rng.gen_range(0..chars.len());
+        match rng.gen_range(0..6) {
+            0 => {
+                // flipped bits of ASCII characters stay in ASCII
+                let bits = if chars[i].is_ascii() { 7 } else { 16 };
+                let flipped = chars[i] as u32 ^ (1 << rng.gen_range(0..bits));
+                if let Some(c) = std::char::from_u32(flipped).filter(|c| !c.is_control()) {
+                    chars[i] = c;
+                }
+            }
+            1 => chars[i] = rng.gen_range(b' '..=b'~') as char,
+            2 => chars.insert(i, rng.gen_range(b' '..=b'~') as char),
+            3 => {
+                chars.remove(i);
+            }
+            4 => chars.insert(i, chars[i]),
+            _ => {
+                let j = if i + 1 < chars.len() { i + 1 } else { i.saturating_sub(1) };
+                chars.swap(i, j);
+            }
+        }
+    }
+    chars.into_iter().collect()
+}
+
+/// Build a plausibly valid email address.
+fn valid_email<R>(rng: &mut R) -> String
+where
+    R: Rng,
+{
+    let len = rng.gen_range(1..=12);
+    let mut local = random_alphanumeric(rng, len);
+    match rng.gen_range(0..4) {
+        0 => {
+            local.push('.');
+            let len = rng.gen_range(1..=8);
+            local.push_str(&random_alphanumeric(rng, len));
+        }
+        1 => {
+            local.push('+');
+            let len = rng.gen_range(1..=8);
+            local.push_str(&random_alphanumeric(rng, len));
+        }
+        _ => {}
+    }
+    let len = rng.gen_range(1..=12);
+    let domain = random_alphanumeric(rng, len).to_lowercase();
+    let tld = EMAIL_TLDS.choose(rng).unwrap();
+    format!("{}@{}.{}", local, domain, tld)
+}
+
+/// Build a deliberately malformed (or at least unusual) email address.
+fn malformed_email<R>(rng: &mut R) -> String
+where
+    R: Rng,
+{
+    let valid = valid_email(rng);
+    let (local, domain) = valid.split_once('@').unwrap();
+    match rng.gen_range(0..12) {
+        0 => format!("{}@@{}", local, domain),
+        1 => format!("{}{}", local, domain),
+        2 => format!("@{}", domain),
+        3 => format!("{}@", local),
+        4 => format!("{}..{}@{}", local, local, domain),
+        5 => format!(".{}@{}", local, domain),
+        // local parts are limited to 64 characters
+        6 => format!("{}@{}", "a".repeat(rng.gen_range(65..=300)), domain),
+        7 => format!("{}@exämple.{}", local, EMAIL_TLDS.choose(rng).unwrap()),
+        8 => format!("\"{} {}\"@{}", local, local, domain),
+        9 => format!("{} {}@{}", local, local, domain),
+        10 => format!("{}@{}.", local, domain),
+        _ => format!("{}@[127.0.0.1]", local),
+    }
+}
+
+/// A regular expression compiled for sampling. As it is compiled from the
+/// pattern of its generator, any two are taken to be equal.
+#[derive(Debug, Clone)]
+pub struct CompiledRegex(rand_regex::Regex);
+
+impl PartialEq for CompiledRegex {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// Compile a regular expression for sampling.
+/// Unicode classes are disabled, so that `\d` and `\w`
+/// only produce ASCII characters.
+fn compile_regex(pattern: &str) -> Result<rand_regex::Regex, DynError> {
+    let hir = regex_syntax::ParserBuilder::new()
+        .unicode(false)
+        .build()
+        .parse(pattern)
+        .map_err(|e| format!("invalid regex pattern '{}': {}", pattern, e))?;
+    rand_regex::Regex::with_hir(hir, REGEX_MAX_REPEAT)
+        .map_err(|e| format!("invalid regex pattern '{}': {}", pattern, e).into())
+}
+
+/// Read the value of an environment variable for the `env` generator,
+/// given by name (with or without a leading `$`).
+fn env_value(var: &str, default: Option<&str>) -> Result<String, DynError> {
+    let name = var.trim_start_matches('$');
+    match std::env::var(name) {
+        Ok(value) => Ok(value),
+        Err(VarError::NotUnicode(_)) => {
+            Err(format!("environment variable '{}' is not valid UTF-8", name).into())
+        }
+        Err(VarError::NotPresent) => default
+            .map(str::to_string)
+            .ok_or_else(|| format!("environment variable '{}' is not set", name).into()),
+    }
+}